@@ -75,6 +75,24 @@
                   default = "/etc/botirage/botirage.db";
                   description = "Path to the database file";
                 };
+
+                owners = lib.mkOption {
+                  type = lib.types.listOf lib.types.int;
+                  default = [ 1908102113 ];
+                  description = "Telegram user ids allowed to run every privileged command";
+                };
+
+                trust_chat_admins = lib.mkOption {
+                  type = lib.types.bool;
+                  default = false;
+                  description = "Also allow each chat's administrators to run privileged commands";
+                };
+
+                payouts_path = lib.mkOption {
+                  type = lib.types.nullOr lib.types.str;
+                  default = null;
+                  description = "Path to a TOML file overriding the emoji games payout tables";
+                };
               };
 
               config = lib.mkIf config.services.botirage.enable {
@@ -85,6 +103,11 @@
                   environment = {
                     TELOXIDE_TOKEN = config.services.botirage.telegram_api_key;
                     DATABASE_PATH = config.services.botirage.database_path;
+                    BOT_OWNERS = lib.concatMapStringsSep "," toString config.services.botirage.owners;
+                    TRUST_CHAT_ADMINS = lib.boolToString config.services.botirage.trust_chat_admins;
+                  }
+                  // lib.optionalAttrs (config.services.botirage.payouts_path != null) {
+                    PAYOUTS_PATH = config.services.botirage.payouts_path;
                   };
                   serviceConfig = {
                     Type = "simple";
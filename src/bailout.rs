@@ -0,0 +1,135 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use teloxide::types::{ChatId, Message, UserId};
+
+use crate::ledger::Reason;
+use crate::state::{BailoutSettings, State};
+use crate::utils::{reply_to, update_state, BotType, DialogueType, HandlerResult};
+
+const MAX_BAILOUT_AMOUNT: i64 = 10_000;
+const MAX_COOLDOWN_HOURS: i64 = 24 * 30;
+
+pub(crate) enum Refusal {
+    NotBroke,
+    TooEarly(DateTime<Utc>),
+}
+
+impl Refusal {
+    // Completes "Baisse ta mise" in the message sent to broke players
+    pub(crate) fn advice(&self) -> String {
+        match self {
+            Self::NotBroke => {
+                " ou essaie de soudoyer le maître du jeu pour obtenir plus de 💵!".to_string()
+            }
+            Self::TooEarly(eligible_at) => format!(
+                " ou patiente, le maître du jeu te renflouera {}",
+                format_until(eligible_at)
+            ),
+        }
+    }
+}
+
+fn format_until(time: &DateTime<Utc>) -> String {
+    let remaining = *time - Utc::now();
+    match remaining {
+        _ if remaining < TimeDelta::minutes(1) => "dans un instant".to_string(),
+        _ if remaining < TimeDelta::hours(1) => format!("dans {} min", remaining.num_minutes()),
+        _ => format!("dans {} h", remaining.num_hours()),
+    }
+}
+
+// Bails out a player who stayed at or below zero for the chat's cooldown
+pub(crate) fn claim_bailout(
+    state: &mut State,
+    chat: ChatId,
+    player: &UserId,
+) -> Result<i64, Refusal> {
+    if *state.get(player) > 0 {
+        return Err(Refusal::NotBroke);
+    }
+    let rules = state.settings().bailout.clone();
+    let now = Utc::now();
+    let profile = state.profile_mut(player);
+    // Players who went broke before their profile tracked it start waiting now
+    let broke_since = *profile.broke_since.get_or_insert(now);
+    let eligible_at = broke_since + TimeDelta::hours(rules.cooldown_hours);
+    if now < eligible_at {
+        return Err(Refusal::TooEarly(eligible_at));
+    }
+
+    profile.bailouts += 1;
+    state.insert(chat, player, rules.amount, Reason::Bailout);
+    Ok(rules.amount)
+}
+
+pub(crate) async fn bailout(bot: BotType, dialogue: DialogueType, msg: Message) -> HandlerResult {
+    let player = msg
+        .from
+        .clone()
+        .ok_or("The message poster has disappeared")?;
+    let bailout = update_state(&dialogue, |state| {
+        claim_bailout(state, msg.chat.id, &player.id)
+    })
+    .await?;
+
+    let username = player.username.unwrap_or(player.first_name);
+    let message = match bailout {
+        Ok(amount) => format!(
+            "@{}, le maître du jeu a pitié de toi et te renfloue de {}💵 🛟",
+            username, amount
+        ),
+        Err(Refusal::NotBroke) => format!("@{}, tu n'es pas fauché, va jouer!", username),
+        Err(Refusal::TooEarly(eligible_at)) => format!(
+            "@{}, encore un peu de patience, tu seras renfloué {}",
+            username,
+            format_until(&eligible_at)
+        ),
+    };
+    reply_to(&bot, &msg, message).await
+}
+
+pub(crate) async fn set_bailout(
+    bot: BotType,
+    dialogue: DialogueType,
+    msg: Message,
+    args: String,
+) -> HandlerResult {
+    const USAGE: &str = "Utilisation: /setbailout <montant> <délai en heures>";
+
+    let rules = match args.split_whitespace().collect::<Vec<_>>()[..] {
+        [] => None,
+        [amount, cooldown_hours] => match (amount.parse(), cooldown_hours.parse()) {
+            (Ok(amount), Ok(cooldown_hours))
+                if (1..=MAX_BAILOUT_AMOUNT).contains(&amount)
+                    && (0..=MAX_COOLDOWN_HOURS).contains(&cooldown_hours) =>
+            {
+                Some(BailoutSettings {
+                    amount,
+                    cooldown_hours,
+                })
+            }
+            _ => {
+                let error = format!(
+                    "Le montant doit être entre 1 et {}💵 et le délai entre 0 et {} heures\n{}",
+                    MAX_BAILOUT_AMOUNT, MAX_COOLDOWN_HOURS, USAGE
+                );
+                return reply_to(&bot, &msg, error).await;
+            }
+        },
+        _ => return reply_to(&bot, &msg, USAGE).await,
+    };
+
+    let rules = update_state(&dialogue, |state| {
+        let bailout = &mut state.settings_mut().bailout;
+        if let Some(rules) = rules {
+            *bailout = rules;
+        }
+        bailout.clone()
+    })
+    .await?;
+
+    let message = format!(
+        "Les joueurs fauchés depuis {} h sont renfloués de {}💵",
+        rules.cooldown_hours, rules.amount
+    );
+    reply_to(&bot, &msg, message).await
+}
@@ -1,15 +1,22 @@
+use std::str::FromStr;
+
 use teloxide::{
-    payloads::{SendMessageSetters, SetMessageReactionSetters},
+    payloads::SendMessageSetters,
     requests::Requester,
-    types::{Message, ReactionType, ReplyParameters},
+    types::{Message, MessageEntityKind, ReplyParameters, User, UserId},
     utils::command::BotCommands,
 };
 
 use crate::{
-    utils::{get_username, BotType, DialogueType, HandlerResult},
+    ledger::{format_elapsed, Reason},
+    utils::{get_username, reply_to, update_state, BotType, DialogueType, HandlerResult},
     Command,
 };
 
+const HISTORY_LENGTH: usize = 10;
+const MAX_GIVE_AMOUNT: i64 = 1_000_000;
+const MAX_PAY_AMOUNT: i64 = 1_000_000;
+
 pub(crate) async fn help(bot: BotType, msg: Message) -> HandlerResult {
     let mut message = bot.send_message(msg.chat.id, Command::descriptions().to_string());
     if let Some(thread_msg_id) = msg.thread_id {
@@ -24,15 +31,57 @@ pub(crate) async fn balance(bot: BotType, dialogue: DialogueType, msg: Message)
     let player = msg.from.ok_or("The message poster has disappeared")?;
     let state = dialogue.get().await?.ok_or("No state")?;
     let player_money = state.get(&player.id);
+    let mut message = format!(
+        "@{}, tu as {}💵!",
+        player.username.unwrap_or(player.first_name),
+        player_money
+    );
+    if let bailouts @ 1.. = state.profile(&player.id).bailouts {
+        message.push_str(&format!(" Renfloué {} fois 🛟", bailouts));
+    }
     let mut message = bot
-        .send_message(
-            msg.chat.id,
-            format!(
-                "@{}, tu as {}💵!",
-                player.username.unwrap_or(player.first_name),
-                player_money
-            ),
-        )
+        .send_message(msg.chat.id, message)
+        .reply_parameters(ReplyParameters::new(msg.id));
+    if let Some(thread_msg_id) = msg.thread_id {
+        message = message.message_thread_id(thread_msg_id);
+    }
+    message.await?;
+
+    Ok(())
+}
+
+pub(crate) async fn history(bot: BotType, dialogue: DialogueType, msg: Message) -> HandlerResult {
+    let player = match replied_to_player(&msg) {
+        Some(player) => player,
+        None => msg
+            .from
+            .clone()
+            .ok_or("The message poster has disappeared")?,
+    };
+    let state = dialogue.get().await?.ok_or("No state")?;
+    let history = state.history(&player.id, HISTORY_LENGTH);
+
+    let username = player.username.unwrap_or(player.first_name);
+    let mut message = match &*history {
+        [] => format!("@{}, aucune transaction pour l'instant", username),
+        _ => format!("Dernières transactions de @{}:\n", username),
+    };
+    for transaction in history {
+        let reason = match &transaction.note {
+            Some(note) => format!("{} ({})", transaction.reason, note),
+            None => transaction.reason.to_string(),
+        };
+        message.push_str(&format!(
+            "{}: {:+}💵 → {}💵 ({})\n",
+            reason,
+            transaction.delta,
+            transaction.balance,
+            format_elapsed(&transaction.timestamp)
+        ));
+    }
+
+    let mut message = bot
+        .send_message(msg.chat.id, message)
         .reply_parameters(ReplyParameters::new(msg.id));
     if let Some(thread_msg_id) = msg.thread_id {
         message = message.message_thread_id(thread_msg_id);
@@ -51,12 +100,17 @@ pub(crate) async fn leaderboard(
     let leaderboard = state.leaderboard();
     let mut message = "Classement ForbeSupélec:\n".to_owned();
     for &(user_id, money) in leaderboard.iter().take(10) {
+        let bailouts = match state.profile(&user_id).bailouts {
+            0 => String::new(),
+            bailouts => format!(" (🛟{})", bailouts),
+        };
         message.push_str(&format!(
-            "{}: {}💵\n",
+            "{}: {}💵{}\n",
             get_username(&bot, msg.chat.id, &user_id)
                 .await
                 .unwrap_or("____".to_string()),
-            money
+            money,
+            bailouts
         ));
     }
 
@@ -69,49 +123,286 @@ pub(crate) async fn leaderboard(
     Ok(())
 }
 
+struct GiveArgs {
+    amount: i64,
+    reason: Option<String>,
+}
+
+impl FromStr for GiveArgs {
+    type Err = String;
+
+    fn from_str(args: &str) -> Result<Self, Self::Err> {
+        let (amount, reason) = args
+            .trim()
+            .split_once(char::is_whitespace)
+            .unwrap_or((args.trim(), ""));
+        let amount = match amount.parse::<i64>() {
+            Ok(0) => return Err("Donner 0💵, c'est radin même pour toi".to_string()),
+            Ok(amount) if amount.abs() > MAX_GIVE_AMOUNT => {
+                return Err(format!(
+                    "Doucement, pas plus de {}💵 à la fois",
+                    MAX_GIVE_AMOUNT
+                ))
+            }
+            Ok(amount) => amount,
+            Err(_) => return Err(format!("{:?} n'est pas un montant valide", amount)),
+        };
+        let reason = Some(reason.trim().to_string()).filter(|reason| !reason.is_empty());
+
+        Ok(Self { amount, reason })
+    }
+}
+
 pub(crate) async fn give_money(
     bot: BotType,
     dialogue: DialogueType,
     msg: Message,
+    args: String,
 ) -> HandlerResult {
-    if msg.clone().from.map(|user| user.id.0) != Some(1908102113) {
-        bot.set_message_reaction(msg.chat.id, msg.id)
-            .reaction(vec![ReactionType::Emoji {
-                emoji: "🤣".to_string(),
-            }])
-            .await?;
+    const USAGE: &str = "Utilisation: /give <montant> [raison], en réponse au message du joueur";
 
-        return Ok(()); // Only the bot owner can give money
+    // Ensure that the msg is a reply to another message
+    let Some(player) = replied_to_player(&msg) else {
+        return reply_to(&bot, &msg, format!("À qui ? {}", USAGE)).await;
+    };
+    let GiveArgs { amount, reason } = match args.parse() {
+        Ok(args) => args,
+        Err(err) => return reply_to(&bot, &msg, format!("{}\n{}", err, USAGE)).await,
+    };
+
+    let balance = update_state(&dialogue, |state| {
+        let kind = if amount > 0 {
+            Reason::Give
+        } else {
+            Reason::Fine
+        };
+        state.insert_with_note(msg.chat.id, &player.id, amount, kind, reason.clone());
+        *state.get(&player.id)
+    })
+    .await?;
+
+    let username = player.username.unwrap_or(player.first_name);
+    let mut message = match amount {
+        _ if amount > 0 => format!("@{} reçoit {}💵", username, amount),
+        _ => format!("@{} est mis à l'amende de {}💵", username, -amount),
+    };
+    if let Some(reason) = reason {
+        message.push_str(&format!(" ({})", reason));
     }
+    message.push_str(&format!(", son solde est maintenant de {}💵", balance));
+    reply_to(&bot, &msg, message).await
+}
 
-    // Ensure that the msg is a reply to another message
-    let reply_to = msg.reply_to_message().ok_or("No message to reply to");
-    let player = match reply_to {
-        Ok(reply) => reply
-            .to_owned()
-            .from
-            .ok_or("The message poster has disappeared")?,
-        Err(_) => {
-            bot.set_message_reaction(msg.chat.id, msg.id)
-                .reaction(vec![ReactionType::Emoji {
-                    emoji: "🤔".to_string(),
-                }])
-                .await?;
-
-            return Ok(());
+// Messages posted in a forum topic reply to its creation message even when the user did not reply.
+// Outside of forums the thread id is the root of the reply chain, so it can be an explicit reply.
+fn replied_to_player(msg: &Message) -> Option<User> {
+    let topic_root = msg
+        .thread_id
+        .filter(|_| msg.is_topic_message)
+        .map(|thread_id| thread_id.0);
+    msg.reply_to_message()
+        .filter(|reply| Some(reply.id) != topic_root && reply.forum_topic_created().is_none())
+        .and_then(|reply| reply.from.clone())
+}
+
+enum Payee {
+    User(User),
+    Username(String),
+}
+
+// The mentioned player, or the author of the replied to message
+fn payee(msg: &Message) -> Option<Payee> {
+    let mentions = msg.parse_entities().unwrap_or_default();
+    let mention = mentions.iter().find_map(|entity| match entity.kind() {
+        MessageEntityKind::TextMention { user } => Some(Payee::User(user.clone())),
+        MessageEntityKind::Mention => Some(Payee::Username(
+            entity.text().trim_start_matches('@').to_string(),
+        )),
+        _ => None,
+    });
+    mention.or_else(|| replied_to_player(msg).map(Payee::User))
+}
+
+// The Bot API cannot look users up by username, so only the players of the chat can be paid by mention
+async fn find_player(
+    bot: &BotType,
+    msg: &Message,
+    players: Vec<UserId>,
+    username: &str,
+) -> Option<User> {
+    for player in players {
+        let Ok(member) = bot.get_chat_member(msg.chat.id, player).await else {
+            continue;
+        };
+        let found = member
+            .user
+            .username
+            .as_deref()
+            .is_some_and(|candidate| candidate.eq_ignore_ascii_case(username));
+        if found {
+            return Some(member.user);
         }
+    }
+    None
+}
+
+pub(crate) async fn pay(
+    bot: BotType,
+    dialogue: DialogueType,
+    msg: Message,
+    args: String,
+) -> HandlerResult {
+    const USAGE: &str =
+        "Utilisation: /pay <montant> @pseudo, ou /pay <montant> en réponse au message du joueur";
+
+    let payer = msg
+        .from
+        .clone()
+        .ok_or("The message poster has disappeared")?;
+    // The amount may come before or after the mention
+    let amount = args
+        .split_whitespace()
+        .find_map(|arg| arg.parse::<i64>().ok());
+    let amount = match amount {
+        Some(amount) if (1..=MAX_PAY_AMOUNT).contains(&amount) => amount,
+        Some(_) => {
+            let error = format!("Le montant doit être entre 1 et {}💵", MAX_PAY_AMOUNT);
+            return reply_to(&bot, &msg, format!("{}\n{}", error, USAGE)).await;
+        }
+        None => return reply_to(&bot, &msg, format!("Combien ? {}", USAGE)).await,
     };
 
-    let mut state = dialogue.get().await?.ok_or("No state")?;
-    state.insert(&player.id, 100);
+    let payee = match payee(&msg) {
+        Some(Payee::User(user)) => user,
+        Some(Payee::Username(username)) => {
+            let state = dialogue.get().await?.ok_or("No state")?;
+            let players = state
+                .leaderboard()
+                .into_iter()
+                .map(|(player, _)| player)
+                .collect();
+            match find_player(&bot, &msg, players, &username).await {
+                Some(user) => user,
+                None => {
+                    let error = format!("Je ne connais pas de joueur @{} ici", username);
+                    return reply_to(&bot, &msg, error).await;
+                }
+            }
+        }
+        None => return reply_to(&bot, &msg, format!("À qui ? {}", USAGE)).await,
+    };
+    if payee.id == payer.id {
+        return reply_to(&bot, &msg, "Se payer soi-même, c'est de la triche").await;
+    }
+    if payee.is_bot {
+        return reply_to(&bot, &msg, "Les bots n'ont pas besoin de 💵").await;
+    }
 
-    bot.set_message_reaction(msg.chat.id, msg.id)
-        .reaction(vec![ReactionType::Emoji {
-            emoji: "👍".to_string(),
-        }])
-        .await?;
+    let payer_name = payer.username.clone().unwrap_or(payer.first_name.clone());
+    let payee_name = payee.username.clone().unwrap_or(payee.first_name.clone());
+    let balances = update_state(&dialogue, |state| {
+        let balance = *state.get(&payer.id);
+        if balance < amount {
+            return Err(balance);
+        }
+        let chat = msg.chat.id;
+        let note = Some(format!("à @{}", payee_name));
+        state.insert_with_note(chat, &payer.id, -amount, Reason::PaymentSent, note);
+        let note = Some(format!("de @{}", payer_name));
+        state.insert_with_note(chat, &payee.id, amount, Reason::PaymentReceived, note);
+        Ok((*state.get(&payer.id), *state.get(&payee.id)))
+    })
+    .await?;
 
-    dialogue.update(state).await?;
+    let message = match balances {
+        Ok((payer_balance, payee_balance)) => format!(
+            "@{} a payé {}💵 à @{}\n@{}: {}💵\n@{}: {}💵",
+            payer_name, amount, payee_name, payer_name, payer_balance, payee_name, payee_balance
+        ),
+        Err(balance) => format!(
+            "@{}, tu n'as que {}💵, impossible de payer {}💵 à @{}",
+            payer_name, balance, amount, payee_name
+        ),
+    };
+    reply_to(&bot, &msg, message).await
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOPIC_ROOT: &str = r#"{"chat":{"id":-1001847508954,"is_forum":true,"title":"twest","type":"supergroup"},"date":1675229139,"forum_topic_created":{"icon_color":9367192,"name":"???"},"from":{"first_name":"Creator","id":1,"is_bot":false,"username":"creator"},"is_topic_message":true,"message_id":4,"message_thread_id":4}"#;
+    const TOPIC_POST: &str = r#"{"chat":{"id":-1001847508954,"is_forum":true,"title":"twest","type":"supergroup"},"date":1675229140,"from":{"first_name":"Poster","id":2,"is_bot":false,"username":"poster"},"is_topic_message":true,"message_id":6,"message_thread_id":4}"#;
+
+    fn message(text: &str, reply_to: Option<&str>, topic: bool, entities: &str) -> Message {
+        let (chat, thread) = match topic {
+            true => (
+                r#"{"id":-1001847508954,"is_forum":true,"title":"twest","type":"supergroup"}"#,
+                r#","is_topic_message":true,"message_thread_id":4"#,
+            ),
+            false => (
+                r#"{"id":-1001847508955,"title":"group","type":"supergroup"}"#,
+                // Replies outside of forums are threaded under the root of the reply chain
+                r#","message_thread_id":6"#,
+            ),
+        };
+        let reply_to = reply_to
+            .map(|reply| format!(r#","reply_to_message":{}"#, reply))
+            .unwrap_or_default();
+        let json = format!(
+            r#"{{"chat":{},"date":1675229141,"from":{{"first_name":"Payer","id":3,"is_bot":false,"username":"payer"}},"message_id":7,"text":{:?},"entities":[{}]{}{}}}"#,
+            chat, text, entities, thread, reply_to
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    const COMMAND: &str = r#"{"type":"bot_command","offset":0,"length":4}"#;
+
+    #[test]
+    fn topic_root_is_not_a_reply() {
+        let msg = message("/pay 20", Some(TOPIC_ROOT), true, COMMAND);
+        assert!(replied_to_player(&msg).is_none());
+        assert!(payee(&msg).is_none());
+    }
+
+    #[test]
+    fn explicit_reply_in_topic() {
+        let msg = message("/pay 20", Some(TOPIC_POST), true, COMMAND);
+        assert!(matches!(payee(&msg), Some(Payee::User(user)) if user.id == UserId(2)));
+    }
+
+    #[test]
+    fn explicit_reply_outside_forums() {
+        let reply = TOPIC_POST.replace(r#","is_topic_message":true,"message_thread_id":4"#, "");
+        let msg = message("/pay 20", Some(&reply), false, COMMAND);
+        assert!(matches!(payee(&msg), Some(Payee::User(user)) if user.id == UserId(2)));
+    }
+
+    #[test]
+    fn mention_takes_precedence() {
+        let entities = format!(r#"{},{{"type":"mention","offset":8,"length":7}}"#, COMMAND);
+        let msg = message("/pay 20 @poster", Some(TOPIC_POST), true, &entities);
+        assert!(matches!(payee(&msg), Some(Payee::Username(username)) if username == "poster"));
+    }
+
+    fn give_args(args: &str) -> Result<(i64, Option<String>), String> {
+        args.parse::<GiveArgs>()
+            .map(|GiveArgs { amount, reason }| (amount, reason))
+    }
+
+    #[test]
+    fn parses_give_arguments() {
+        assert_eq!(give_args("10"), Ok((10, None)));
+        assert_eq!(give_args("-5 retard"), Ok((-5, Some("retard".to_string()))));
+        assert_eq!(
+            give_args("  20   pour le bar  "),
+            Ok((20, Some("pour le bar".to_string())))
+        );
+        assert_eq!(give_args("1000000"), Ok((MAX_GIVE_AMOUNT, None)));
+
+        assert!(give_args("0").is_err());
+        assert!(give_args("-1000001").is_err());
+        assert!(give_args("dix").is_err());
+        assert!(give_args("").is_err());
+    }
 }
@@ -0,0 +1,113 @@
+use chrono::Utc;
+use teloxide::types::Message;
+
+use crate::ledger::Reason;
+use crate::state::DailySettings;
+use crate::utils::{
+    reply_to, update_state, BotType, DialogueType, HandlerResult, DEFAULT_TIMEZONE,
+};
+
+const MAX_DAILY_AMOUNT: i64 = 10_000;
+const MAX_STREAK: u32 = 365;
+
+pub(crate) async fn daily(bot: BotType, dialogue: DialogueType, msg: Message) -> HandlerResult {
+    let player = msg
+        .from
+        .clone()
+        .ok_or("The message poster has disappeared")?;
+    let today = Utc::now().with_timezone(&DEFAULT_TIMEZONE).date_naive();
+
+    let claim = update_state(&dialogue, |state| {
+        let rules = state.settings().daily.clone();
+        let profile = state.profile_mut(&player.id);
+        if profile.last_daily == Some(today) {
+            return Err(profile.daily_streak);
+        }
+        let streak = match profile.last_daily {
+            Some(last_daily) if last_daily.succ_opt() == Some(today) => profile.daily_streak + 1,
+            _ => 1,
+        };
+        profile.last_daily = Some(today);
+        profile.daily_streak = streak;
+
+        let bonus_days = streak.min(rules.max_streak).saturating_sub(1);
+        let amount = rules.amount + rules.streak_bonus * bonus_days as i64;
+        let note = Some(format!("{} jours d'affilée", streak)).filter(|_| streak > 1);
+        state.insert_with_note(msg.chat.id, &player.id, amount, Reason::Daily, note);
+        Ok((amount, streak, *state.get(&player.id)))
+    })
+    .await?;
+
+    let username = player.username.unwrap_or(player.first_name);
+    let message = match claim {
+        Ok((amount, 1, balance)) => format!(
+            "@{}, voici ton allocation du jour: +{}💵, tu as maintenant {}💵",
+            username, amount, balance
+        ),
+        Ok((amount, streak, balance)) => format!(
+            "@{}, voici ton allocation du jour: +{}💵 ({} jours d'affilée 🔥), tu as maintenant {}💵",
+            username, amount, streak, balance
+        ),
+        Err(streak) => format!(
+            "@{}, tu as déjà touché ton allocation aujourd'hui, reviens demain pour prolonger ta série de {} jour(s)",
+            username, streak
+        ),
+    };
+    reply_to(&bot, &msg, message).await
+}
+
+pub(crate) async fn set_daily(
+    bot: BotType,
+    dialogue: DialogueType,
+    msg: Message,
+    args: String,
+) -> HandlerResult {
+    const USAGE: &str =
+        "Utilisation: /setdaily <montant> <bonus par jour de série> <jours de série max>";
+
+    let rules = match args.split_whitespace().collect::<Vec<_>>()[..] {
+        [] => None,
+        [amount, streak_bonus, max_streak] => {
+            let amount = amount.parse::<i64>().ok();
+            let streak_bonus = streak_bonus.parse::<i64>().ok();
+            let max_streak = max_streak.parse::<u32>().ok();
+            match (amount, streak_bonus, max_streak) {
+                (Some(amount), Some(streak_bonus), Some(max_streak))
+                    if (0..=MAX_DAILY_AMOUNT).contains(&amount)
+                        && (0..=MAX_DAILY_AMOUNT).contains(&streak_bonus)
+                        && (1..=MAX_STREAK).contains(&max_streak) =>
+                {
+                    Some(DailySettings {
+                        amount,
+                        streak_bonus,
+                        max_streak,
+                    })
+                }
+                _ => {
+                    let error = format!(
+                        "Les montants doivent être entre 0 et {}💵 et la série entre 1 et {} jours\n{}",
+                        MAX_DAILY_AMOUNT, MAX_STREAK, USAGE
+                    );
+                    return reply_to(&bot, &msg, error).await;
+                }
+            }
+        }
+        _ => return reply_to(&bot, &msg, USAGE).await,
+    };
+
+    let rules = update_state(&dialogue, |state| {
+        let daily = &mut state.settings_mut().daily;
+        if let Some(rules) = rules {
+            *daily = rules;
+        }
+        daily.clone()
+    })
+    .await?;
+
+    let max_amount = rules.amount + rules.streak_bonus * (rules.max_streak - 1) as i64;
+    let message = format!(
+        "Allocation quotidienne: {}💵, +{}💵 par jour de série, jusqu'à {}💵 après {} jours",
+        rules.amount, rules.streak_bonus, max_amount, rules.max_streak
+    );
+    reply_to(&bot, &msg, message).await
+}
@@ -1,132 +1,353 @@
+use std::time::Duration;
+
+use log::warn;
 use teloxide::{
     payloads::SetMessageReactionSetters,
     requests::Requester,
-    types::{Dice, DiceEmoji, Message, MessageDice, MessageKind, ReactionType},
+    types::{Dice, Message, MessageDice, MessageKind, ReactionType},
 };
 
-use crate::utils::{BotType, DialogueType, HandlerResult};
+use crate::bailout::claim_bailout;
+use crate::payouts::{DiceCall, Game, Payout, PayoutsType, SlotSymbol};
+use crate::scheduler::{JobKind, SchedulerType};
+use crate::utils::{reply_to, update_state, BotType, DialogueType, HandlerResult};
+
+const MAX_PAYOUT: i64 = 1000;
+const MAX_STAKE: i64 = 1000;
 
 pub(crate) async fn emoji_games_handler(
     bot: BotType,
     dialogue: DialogueType,
+    scheduler: SchedulerType,
+    payouts: PayoutsType,
     msg: Message,
 ) -> HandlerResult {
     let mut state = dialogue.get().await?.ok_or("No state")?;
-    let player = msg.from.ok_or("The message poster has disappeared")?;
-    if msg.chat.id.0 != -1001434638400 && state.get(&player.id) < &1 {
-        bot.send_message(
-            msg.chat.id,
-            format!(
-                "@{}, tu n'as plus assez d'argent pour jouer! Essaie de soudoyer le maître du jeu pour obtenir plus de 💵!",
-                player.username.unwrap_or(player.first_name)
-            ),
-        )
-        .await?;
-        bot.delete_message(msg.chat.id, msg.id).await?;
-        return Ok(());
-    }
-
-    let dice_message = match msg.kind {
+    let player = msg
+        .from
+        .clone()
+        .ok_or("The message poster has disappeared")?;
+    let username = player.username.clone().unwrap_or(player.first_name.clone());
+    let dice_message = match &msg.kind {
         MessageKind::Dice(MessageDice { dice: dice_message }) => dice_message,
         _ => unreachable!(),
     };
+    let Dice { emoji, value } = dice_message.clone();
+    let game = Game::from_emoji(emoji);
+    // A 🎲 thrown without a call is not a bet, the balance does not matter
+    let dice_call = state.profile(&player.id).dice_call;
+    if let (Game::Dice, None) = (game, dice_call) {
+        return reply_to(
+            &bot,
+            &msg,
+            "Annonce ton résultat avant de lancer le 🎲: /call <1-6|over|under>",
+        )
+        .await;
+    }
 
-    let (emoji, value) = match dice_message {
-        Dice { emoji, value } => (emoji, value),
-    };
+    let practice_mode = state.settings().practice_mode;
+    let stake = state.profile(&player.id).stake();
+    // Some outcomes cost several times the stake, the player must be able to pay the worst one
+    let risk = stake * (-payouts.worst_score(game, state.settings())).max(1);
+    if !practice_mode && state.get(&player.id) < &risk {
+        let bailout = update_state(&dialogue, |state| {
+            claim_bailout(state, msg.chat.id, &player.id)
+        })
+        .await?;
+        let advice = match bailout {
+            Ok(amount) => {
+                let message = format!(
+                    "@{}, tu es fauché depuis trop longtemps, le maître du jeu te renfloue de {}💵 🛟",
+                    username, amount
+                );
+                reply_to(&bot, &msg, message).await?;
+                state = dialogue.get().await?.ok_or("No state")?;
+                "!".to_string()
+            }
+            Err(refusal) => refusal.advice(),
+        };
+
+        if state.get(&player.id) < &risk {
+            let risk = Some(format!(" (tu risques de perdre {}💵)", risk))
+                .filter(|_| risk > stake)
+                .unwrap_or_default();
+            bot.send_message(
+                msg.chat.id,
+                format!(
+                    "@{}, tu n'as plus assez d'argent pour miser {}💵{}! Baisse ta mise{}",
+                    username, stake, risk, advice
+                ),
+            )
+            .await?;
+            bot.delete_message(msg.chat.id, msg.id).await?;
+            return Ok(());
+        }
+    }
 
-    let (reaction, score, delay) = match emoji {
-        DiceEmoji::SlotMachine => slot_machine_handler(value),
-        DiceEmoji::Darts => darts_handler(value),
-        DiceEmoji::Basketball => basketball_handler(value),
-        DiceEmoji::Bowling => bowling_handler(value),
-        DiceEmoji::Football => football_handler(value),
-        _ => return Ok(()),
+    let payout = match (game, dice_call) {
+        (Game::Dice, Some(call)) => payouts.get_dice(call, value, state.settings()),
+        _ => payouts.get(game, value, state.settings()),
     };
+    let Payout {
+        reaction,
+        score,
+        delay,
+    } = payout.ok_or("Dice value out of range")?;
+    let gain = score * stake;
 
-    tokio::spawn(async move {
-        tokio::time::sleep(std::time::Duration::from_secs(delay)).await;
-        bot.set_message_reaction(msg.chat.id, msg.id)
-            .reaction(vec![ReactionType::Emoji {
-                emoji: reaction.to_string(),
-            }])
-            .await
-    });
+    let chat_id = msg.chat.id;
+    let job_bot = bot.clone();
+    scheduler.schedule(
+        chat_id,
+        JobKind::Reaction,
+        Duration::from_secs(delay),
+        async move {
+            let reaction = job_bot
+                .set_message_reaction(msg.chat.id, msg.id)
+                .reaction(vec![ReactionType::Emoji { emoji: reaction }]);
+            if let Err(err) = reaction.await {
+                warn!("Failed to react to a game: {}", err);
+            }
+            let mut result = vec![];
+            if game == Game::SlotMachine {
+                let reels = SlotSymbol::decode(value).map(|symbol| symbol.to_string());
+                result.push(reels.join(" "));
+            }
+            if let (Game::Dice, Some(call)) = (game, dice_call) {
+                result.push(format!("annonce {}", call));
+            }
+            if stake != 1 {
+                result.push(format!("mise de {}💵", stake));
+            }
+            if !result.is_empty() {
+                let result = match practice_mode {
+                    true => format!(
+                        "{} → {:+}💵 pour de faux, mode entraînement 🏋️",
+                        result.join(", "),
+                        gain
+                    ),
+                    false => format!("{} → {:+}💵", result.join(", "), gain),
+                };
+                if let Err(err) = reply_to(&job_bot, &msg, result).await {
+                    warn!("Failed to announce a game result: {}", err);
+                }
+            }
+        },
+    );
 
-    state.insert(&player.id, score);
-    dialogue.update(state).await?;
+    update_state(&dialogue, |state| {
+        state.profile_mut(&player.id).next_stake = None;
+        if !practice_mode {
+            let note = Some(format!("mise de {}💵", stake)).filter(|_| stake != 1);
+            state.insert_with_note(chat_id, &player.id, gain, game.reason(), note);
+        }
+    })
+    .await?;
 
     Ok(())
 }
 
-fn slot_machine_handler(value: u8) -> (&'static str, i64, u64) {
-    let value = value - 1;
-    let (left, middle, right) = (
-        (value >> 4) & 0b11,
-        (value >> 2) & 0b11,
-        (value >> 0) & 0b11,
-    );
+pub(crate) async fn set_stake(
+    bot: BotType,
+    dialogue: DialogueType,
+    msg: Message,
+    args: String,
+) -> HandlerResult {
+    set_player_stake(bot, dialogue, msg, args, false).await
+}
 
-    let (reaction, score) = match (left, middle, right) {
-        (3, 3, 3) => ("🔥", 30),
-        _ if left == middle && left == right => ("🎉", 10),
-        _ if left == middle || middle == right || left == right => ("😢", -1),
-        _ => ("🥱", -1),
+pub(crate) async fn set_default_stake(
+    bot: BotType,
+    dialogue: DialogueType,
+    msg: Message,
+    args: String,
+) -> HandlerResult {
+    set_player_stake(bot, dialogue, msg, args, true).await
+}
+
+async fn set_player_stake(
+    bot: BotType,
+    dialogue: DialogueType,
+    msg: Message,
+    args: String,
+    default: bool,
+) -> HandlerResult {
+    let player = msg
+        .from
+        .clone()
+        .ok_or("The message poster has disappeared")?;
+    let stake = match args.trim() {
+        "" | "reset" => None,
+        stake => match stake.parse::<i64>() {
+            Ok(stake) if (1..=MAX_STAKE).contains(&stake) => Some(stake),
+            _ => {
+                let error = format!(
+                    "{:?} n'est pas une mise valide, elle doit être entre 1 et {}💵",
+                    stake, MAX_STAKE
+                );
+                return reply_to(&bot, &msg, error).await;
+            }
+        },
     };
 
-    (reaction, score, 2)
+    let (balance, profile) = update_state(&dialogue, |state| {
+        let profile = state.profile_mut(&player.id);
+        if default {
+            profile.default_stake = stake;
+        } else {
+            profile.next_stake = stake;
+        }
+        (*state.get(&player.id), state.profile(&player.id))
+    })
+    .await?;
+
+    let mut message = match (default, stake) {
+        (false, Some(stake)) => format!("Ton prochain lancer se joue à {}💵", stake),
+        (false, None) => format!(
+            "Mise annulée, ton prochain lancer se joue à {}💵",
+            profile.stake()
+        ),
+        (true, _) => format!("Tes lancers se jouent désormais à {}💵", profile.stake()),
+    };
+    if balance < profile.stake() {
+        message.push_str(&format!(", mais tu n'as que {}💵", balance));
+    }
+    reply_to(&bot, &msg, message).await
 }
 
-fn darts_handler(value: u8) -> (&'static str, i64, u64) {
-    let (reaction, score) = match value {
-        1 => ("🤡", -2),
-        2 => ("🥱", -2),
-        3 => ("🤔", -2),
-        4 => ("👀", -2),
-        5 => ("🙊", -2),
-        6 => ("😎", 12),
-        _ => unreachable!(),
+pub(crate) async fn set_dice_call(
+    bot: BotType,
+    dialogue: DialogueType,
+    msg: Message,
+    args: String,
+) -> HandlerResult {
+    let player = msg
+        .from
+        .clone()
+        .ok_or("The message poster has disappeared")?;
+    let call = match args.trim() {
+        "" | "reset" => None,
+        call => match call.parse::<DiceCall>() {
+            Ok(call) => Some(call),
+            Err(err) => return reply_to(&bot, &msg, err).await,
+        },
     };
 
-    (reaction, score, 3)
-}
+    update_state(&dialogue, |state| {
+        state.profile_mut(&player.id).dice_call = call;
+    })
+    .await?;
 
-fn basketball_handler(value: u8) -> (&'static str, i64, u64) {
-    let (reaction, score) = match value {
-        1 => ("🫡", -3),
-        2 => ("🥱", -3),
-        3 => ("🥴", -3),
-        4 => ("🆒", 4),
-        5 => ("🤝", 6),
-        _ => unreachable!(),
+    let message = match call {
+        Some(call) => format!("C'est noté, tes prochains 🎲 parient sur {}", call),
+        None => "Annonce retirée, lance /call avant ton prochain 🎲".to_string(),
     };
+    reply_to(&bot, &msg, message).await
+}
 
-    (reaction, score, 4)
+pub(crate) async fn toggle_practice_mode(
+    bot: BotType,
+    dialogue: DialogueType,
+    msg: Message,
+) -> HandlerResult {
+    let practice_mode = update_state(&dialogue, |state| {
+        let settings = state.settings_mut();
+        settings.practice_mode = !settings.practice_mode;
+        settings.practice_mode
+    })
+    .await?;
+
+    let message = if practice_mode {
+        "Mode entraînement activé 🏋️ les jeux ne coûtent et ne rapportent plus rien"
+    } else {
+        "Mode entraînement désactivé, on rejoue pour de vrai 💵"
+    };
+    reply_to(&bot, &msg, message).await
 }
 
-fn bowling_handler(value: u8) -> (&'static str, i64, u64) {
-    let (reaction, score) = match value {
-        1 => ("🌚", -3),
-        2 => ("👨‍💻", -3),
-        3 => ("🦄", -3),
-        4 => ("😨", -3),
-        5 => ("🤨", -3),
-        6 => ("🗿", 16),
-        _ => unreachable!(),
+pub(crate) async fn show_payouts(
+    bot: BotType,
+    dialogue: DialogueType,
+    payouts: PayoutsType,
+    msg: Message,
+    args: String,
+) -> HandlerResult {
+    let games = match args.trim() {
+        "" => Game::ALL.to_vec(),
+        game => match game.parse() {
+            Ok(game) => vec![game],
+            Err(err) => return reply_to(&bot, &msg, err).await,
+        },
     };
 
-    (reaction, score, 4)
+    let state = dialogue.get().await?.ok_or("No state")?;
+    let table = games
+        .into_iter()
+        .map(|game| payouts.describe(game, state.settings()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    reply_to(
+        &bot,
+        &msg,
+        format!(
+            "Gains pour une mise de 1💵 (✏️ = modifié ici):\n\n{}",
+            table
+        ),
+    )
+    .await
 }
 
-fn football_handler(value: u8) -> (&'static str, i64, u64) {
-    let (reaction, score) = match value {
-        1 => ("🌭", -5),
-        2 => ("🐳", -5),
-        3 => ("💅", 1),
-        4 => ("🙏", 5),
-        5 => ("👏", 5),
-        _ => unreachable!(),
+pub(crate) async fn set_payout(
+    bot: BotType,
+    dialogue: DialogueType,
+    payouts: PayoutsType,
+    msg: Message,
+    args: String,
+) -> HandlerResult {
+    const USAGE: &str =
+        "Utilisation: /setpayout <jeu> <valeur du dé ou numéro de combinaison> <gain|reset>";
+
+    let (game, value, score) = match args.split_whitespace().collect::<Vec<_>>()[..] {
+        [game, value, score] => (game, value, score),
+        _ => return reply_to(&bot, &msg, USAGE).await,
+    };
+    let game: Game = match game.parse() {
+        Ok(game) => game,
+        Err(err) => return reply_to(&bot, &msg, format!("{}\n{}", err, USAGE)).await,
     };
+    let keys = payouts.keys(game);
+    let value = match value.parse::<u8>() {
+        Ok(value) if keys.contains(&value) => value,
+        _ => {
+            let error = format!(
+                "{:?} n'est pas une valeur possible pour {}, elle doit être entre {} et {} (voir /payouts)",
+                value,
+                game,
+                keys.start(),
+                keys.end()
+            );
+            return reply_to(&bot, &msg, error).await;
+        }
+    };
+    let score = match score {
+        "reset" => None,
+        score => match score.parse::<i64>() {
+            Ok(score) if score.abs() <= MAX_PAYOUT => Some(score),
+            _ => {
+                let error = format!(
+                    "{:?} n'est pas un gain valide, il doit être entre -{} et {}",
+                    score, MAX_PAYOUT, MAX_PAYOUT
+                );
+                return reply_to(&bot, &msg, error).await;
+            }
+        },
+    };
+
+    let description = update_state(&dialogue, |state| {
+        payouts.set_override(game, value, score, state.settings_mut());
+        payouts.describe(game, state.settings())
+    })
+    .await?;
 
-    (reaction, score, 4)
+    reply_to(&bot, &msg, format!("C'est noté!\n\n{}", description)).await
 }
@@ -0,0 +1,205 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+};
+
+use chrono::{DateTime, Utc};
+use teloxide::types::{ChatId, UserId};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) enum Reason {
+    Opening,
+    SlotMachine,
+    Darts,
+    Basketball,
+    Bowling,
+    Football,
+    Dice,
+    LotoWin,
+    LotoLoss,
+    LotoBet,
+    LotoRefund,
+    Give,
+    Fine,
+    Daily,
+    Bailout,
+    PaymentSent,
+    PaymentReceived,
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            Self::Opening => "Solde initial",
+            Self::SlotMachine => "Machine à sous",
+            Self::Darts => "Fléchettes",
+            Self::Basketball => "Basket",
+            Self::Bowling => "Bowling",
+            Self::Football => "Foot",
+            Self::Dice => "Dé",
+            Self::LotoWin => "Loto gagné",
+            Self::LotoLoss => "Loto perdu",
+            Self::LotoBet => "Mise au loto",
+            Self::LotoRefund => "Mise au loto remboursée",
+            Self::Give => "Don du maître du jeu",
+            Self::Fine => "Amende",
+            Self::Daily => "Allocation quotidienne",
+            Self::Bailout => "Renflouement",
+            Self::PaymentSent => "Paiement envoyé",
+            Self::PaymentReceived => "Paiement reçu",
+        };
+        f.write_str(label)
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct Transaction {
+    pub(crate) player: UserId,
+    pub(crate) chat: ChatId,
+    pub(crate) reason: Reason,
+    pub(crate) delta: i64,
+    pub(crate) balance: i64,
+    pub(crate) timestamp: DateTime<Utc>,
+    #[serde(default)]
+    pub(crate) note: Option<String>,
+}
+
+// Transactions kept per player, the state is saved on every change so it must not grow forever
+const LEDGER_LENGTH: usize = 100;
+
+// Append-only: entries are never modified once recorded, only the oldest ones are dropped.
+// Each entry records the balance it led to, so the last one always matches the player's balance.
+// Saved as a flat list since every transaction names its player, the index is rebuilt on load
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(from = "Vec<Transaction>", into = "Vec<Transaction>")]
+pub(crate) struct Ledger(HashMap<UserId, VecDeque<Transaction>>);
+
+impl From<Vec<Transaction>> for Ledger {
+    fn from(transactions: Vec<Transaction>) -> Self {
+        let mut ledger = Self::default();
+        for transaction in transactions {
+            ledger.push(transaction);
+        }
+        ledger
+    }
+}
+
+impl From<Ledger> for Vec<Transaction> {
+    fn from(ledger: Ledger) -> Self {
+        ledger.0.into_values().flatten().collect()
+    }
+}
+
+impl Ledger {
+    fn push(&mut self, transaction: Transaction) {
+        let transactions = self.0.entry(transaction.player).or_default();
+        transactions.push_back(transaction);
+        if transactions.len() > LEDGER_LENGTH {
+            transactions.pop_front();
+        }
+    }
+
+    pub(crate) fn has_entries(&self, player: &UserId) -> bool {
+        self.0.contains_key(player)
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        chat: ChatId,
+        player: &UserId,
+        reason: Reason,
+        delta: i64,
+        balance: i64,
+        note: Option<String>,
+    ) {
+        self.push(Transaction {
+            player: *player,
+            chat,
+            reason,
+            delta,
+            balance,
+            timestamp: Utc::now(),
+            note,
+        });
+    }
+
+    pub(crate) fn history(&self, player: &UserId, count: usize) -> Vec<&Transaction> {
+        self.0
+            .get(player)
+            .into_iter()
+            .flatten()
+            .rev()
+            .filter(|transaction| transaction.reason != Reason::Opening)
+            .take(count)
+            .collect()
+    }
+
+    pub(crate) fn balance(&self, player: &UserId) -> Option<i64> {
+        let transaction = self.0.get(player)?.back()?;
+        Some(transaction.balance)
+    }
+}
+
+pub(crate) fn format_elapsed(timestamp: &DateTime<Utc>) -> String {
+    let elapsed = Utc::now().signed_duration_since(timestamp);
+    match elapsed {
+        _ if elapsed.num_minutes() < 1 => "à l'instant".to_string(),
+        _ if elapsed.num_hours() < 1 => format!("il y a {} min", elapsed.num_minutes()),
+        _ if elapsed.num_days() < 1 => format!("il y a {} h", elapsed.num_hours()),
+        _ => format!("il y a {} j", elapsed.num_days()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(player: u64, delta: i64, balance: i64) -> Transaction {
+        Transaction {
+            player: UserId(player),
+            chat: ChatId(-1),
+            reason: Reason::Give,
+            delta,
+            balance,
+            timestamp: Utc::now(),
+            note: None,
+        }
+    }
+
+    #[test]
+    fn rebuilds_the_index_from_the_saved_list() {
+        let flat = vec![
+            transaction(1, 10, 110),
+            transaction(2, -5, 95),
+            transaction(1, 20, 130),
+        ];
+        let ledger: Ledger = serde_json::from_value(serde_json::to_value(&flat).unwrap()).unwrap();
+
+        assert_eq!(ledger.balance(&UserId(1)), Some(130));
+        assert_eq!(ledger.balance(&UserId(2)), Some(95));
+        let deltas = ledger
+            .history(&UserId(1), 10)
+            .iter()
+            .map(|t| t.delta)
+            .collect::<Vec<_>>();
+        assert_eq!(deltas, [20, 10]);
+
+        let saved = serde_json::to_string(&ledger).unwrap();
+        let reloaded: Ledger = serde_json::from_str(&saved).unwrap();
+        assert_eq!(reloaded.balance(&UserId(1)), Some(130));
+        assert_eq!(reloaded.history(&UserId(1), 10).len(), 2);
+    }
+
+    #[test]
+    fn keeps_the_last_entries_of_each_player() {
+        let mut ledger = Ledger::default();
+        for balance in 1..=LEDGER_LENGTH as i64 + 10 {
+            ledger.record(ChatId(-1), &UserId(1), Reason::Give, 1, balance, None);
+        }
+        ledger.record(ChatId(-1), &UserId(2), Reason::Give, 1, 1, None);
+
+        assert_eq!(ledger.history(&UserId(1), usize::MAX).len(), LEDGER_LENGTH);
+        assert_eq!(ledger.balance(&UserId(1)), Some(LEDGER_LENGTH as i64 + 10));
+        assert_eq!(ledger.history(&UserId(2), usize::MAX).len(), 1);
+    }
+}
@@ -1,112 +1,696 @@
 use std::{
     collections::HashMap,
     error::Error,
+    fmt::Display,
+    ops::RangeInclusive,
+    str::FromStr,
     sync::{Arc, Mutex},
 };
 
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{info, warn};
 use teloxide::{
     adaptors::Throttle,
     payloads::{SendDiceSetters, SendMessageSetters, SendPollSetters, UnpinChatMessageSetters},
     requests::Requester,
-    types::{Dice, DiceEmoji, Message, MessageDice, MessageKind, PollAnswer, UserId},
+    types::{
+        ChatId, Dice, DiceEmoji, Message, MessageDice, MessageId, MessageKind, PollAnswer,
+        ThreadId, UserId,
+    },
     Bot,
 };
 
-use crate::state::State;
-use crate::utils::{get_usernames, BotType, DialogueType, HandlerResult};
+use crate::ledger::Reason;
+use crate::loto_history::{LotoDraw, LotoEntry};
+use crate::loto_keyboard::{bet_keyboard, describe_bets};
+use crate::scheduler::{JobHandle, JobKind, SchedulerType};
+use crate::state::{LotoSettings, State};
+use crate::utils::{
+    get_username, get_usernames, reply_to, update_state, BotType, DialogueType, HandlerResult,
+    StorageType,
+};
+
+// Chat of every running lottery, keyed by poll id
+pub(crate) type LotoPolls = Arc<Mutex<HashMap<String, ChatId>>>;
+
+// Lotteries overdue by more than this when the bot starts are cancelled instead of drawn
+const STALE_LOTO_HOURS: i64 = 1;
+
+const DEFAULT_LOTO_SECONDS: i64 = 60;
+const LOTO_SECONDS: RangeInclusive<i64> = 10..=3600;
+const MAX_LOTO_AMOUNT: i64 = 10_000;
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+)]
+pub(crate) enum LotoMode {
+    // Bet on a single die, the winners split the pot
+    #[default]
+    Single,
+    // Bet on the sum of two dice, the winners are paid by the odds of their sum
+    Sum,
+}
+
+impl LotoMode {
+    fn dice_count(&self) -> usize {
+        match self {
+            Self::Single => 1,
+            Self::Sum => 2,
+        }
+    }
+
+    pub(crate) fn values(&self) -> RangeInclusive<u8> {
+        match self {
+            Self::Single => 1..=6,
+            Self::Sum => 2..=12,
+        }
+    }
+
+    // Fixed odds payout of a winning stake, none for a single die where the winners split the pot
+    fn odds_payout(&self, stake: i64, value: u8) -> Option<i64> {
+        match self {
+            Self::Single => None,
+            Self::Sum => {
+                // Number of the 36 throws of two dice giving this sum
+                let ways = 6 - (7 - value as i64).abs();
+                Some(stake * 36 / ways)
+            }
+        }
+    }
+}
+
+impl FromStr for LotoMode {
+    type Err = ();
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode.to_lowercase().as_str() {
+            "simple" | "single" => Ok(Self::Single),
+            "somme" | "sum" => Ok(Self::Sum),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub(crate) enum Ballot {
+    // Native Telegram poll, voters pay the entry fee at the draw
+    #[default]
+    Poll,
+    // Inline keyboard, players choose their stake and pay it when betting
+    Keyboard,
+}
+
+impl FromStr for Ballot {
+    type Err = ();
+
+    fn from_str(ballot: &str) -> Result<Self, Self::Err> {
+        match ballot.to_lowercase().as_str() {
+            "sondage" | "poll" => Ok(Self::Poll),
+            "clavier" | "keyboard" => Ok(Self::Keyboard),
+            _ => Err(()),
+        }
+    }
+}
+
+// Duration, mode and ballot of a lottery, as given to /roll
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct LotoRound {
+    pub(crate) duration: i64,
+    pub(crate) mode: LotoMode,
+    #[serde(default)]
+    pub(crate) ballot: Ballot,
+}
+
+impl FromStr for LotoRound {
+    type Err = String;
+
+    fn from_str(args: &str) -> Result<Self, Self::Err> {
+        let mut round = Self {
+            duration: DEFAULT_LOTO_SECONDS,
+            mode: LotoMode::default(),
+            ballot: Ballot::default(),
+        };
+        for arg in args.split_whitespace() {
+            if let Ok(mode) = arg.parse() {
+                round.mode = mode;
+                continue;
+            }
+            if let Ok(ballot) = arg.parse() {
+                round.ballot = ballot;
+                continue;
+            }
+            match arg.parse::<i64>() {
+                Ok(duration) if LOTO_SECONDS.contains(&duration) => round.duration = duration,
+                _ => {
+                    return Err(format!(
+                        "{:?} n'est ni une durée valide (entre {} et {} secondes) ni un mode de jeu (simple, somme, sondage, clavier)",
+                        arg,
+                        LOTO_SECONDS.start(),
+                        LOTO_SECONDS.end()
+                    ))
+                }
+            }
+        }
+        Ok(round)
+    }
+}
+
+impl Display for LotoRound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mode = match self.mode {
+            LotoMode::Single => "un dé",
+            LotoMode::Sum => "somme de deux dés",
+        };
+        write!(f, "{}, {}", format_duration(self.duration), mode)?;
+        if self.ballot == Ballot::Keyboard {
+            f.write_str(", au clavier")?;
+        }
+        Ok(())
+    }
+}
 
 pub(crate) async fn start_loto(
     bot: BotType,
     dialogue: DialogueType,
-    poll_answers: Arc<Mutex<HashMap<UserId, u8>>>,
+    loto_polls: LotoPolls,
+    scheduler: SchedulerType,
     msg: Message,
+    args: String,
 ) -> HandlerResult {
-    poll_answers
-        .lock()
-        .unwrap_or_else(|err| err.into_inner())
-        .clear();
+    let round = match args.parse() {
+        Ok(round) => round,
+        Err(err) => return reply_to(&bot, &msg, err).await,
+    };
+    if !open_loto(
+        bot.clone(),
+        dialogue,
+        loto_polls,
+        &scheduler,
+        msg.thread_id,
+        round,
+    )
+    .await?
+    {
+        return reply_to(&bot, &msg, "Une loterie est déjà en cours").await;
+    }
+    Ok(())
+}
+
+// Returns false without sending anything when a lottery is already running in the chat
+pub(crate) async fn open_loto(
+    bot: BotType,
+    dialogue: DialogueType,
+    loto_polls: LotoPolls,
+    scheduler: &SchedulerType,
+    thread_id: Option<ThreadId>,
+    LotoRound {
+        duration,
+        mode,
+        ballot,
+    }: LotoRound,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let chat_id = dialogue.chat_id();
+    let deadline = Utc::now() + TimeDelta::seconds(duration);
+    // Claimed under the lock, a scheduled lottery and a /roll cannot both open
+    let opened = update_state(&dialogue, |state| match state {
+        State::Idle { .. } => {
+            *state = std::mem::take(state).into_receiving_poll_answers(deadline, mode);
+            Some((state.settings().loto.clone(), state.jackpot()))
+        }
+        State::ReceivingPollAnswers { .. } => None,
+    })
+    .await?;
+    let Some((rules, jackpot)) = opened else {
+        return Ok(false);
+    };
 
-    let state = dialogue.get().await?.ok_or("No state")?;
-    let mut poll = bot
-        .send_poll(
-            msg.chat.id,
-            "Placez vos paris! Vous avez 1 minute.",
-            (1..=6).map(|x| x.to_string()),
-        )
-        .is_anonymous(false);
+    let mut question = match mode {
+        LotoMode::Single => "Placez vos paris! ".to_string(),
+        LotoMode::Sum => {
+            "Pariez sur la somme de deux dés, plus elle est rare plus elle rapporte! ".to_string()
+        }
+    };
+    question.push_str(&format!(
+        "Vous avez {}. {}",
+        format_duration(duration),
+        describe_rules(&rules)
+    ));
+    if jackpot > 0 {
+        question.push_str(&format!(", cagnotte reportée: {}💵", jackpot));
+    }
 
-    if let Some(thread_msg_id) = msg.thread_id {
-        poll = poll.message_thread_id(thread_msg_id);
+    let poll = match send_ballot(&bot, chat_id, thread_id, question, mode, ballot, &rules).await {
+        Ok(poll) => poll,
+        Err(err) => {
+            update_state(&dialogue, |state| {
+                if matches!(state, State::ReceivingPollAnswers { poll: None, .. }) {
+                    *state = std::mem::take(state).into_idle();
+                }
+            })
+            .await?;
+            return Err(err);
+        }
+    };
+    let poll_id = poll.id;
+    let telegram_poll_id = poll.poll().map(|poll| poll.id.clone());
+    let stored = update_state(&dialogue, |state| match state {
+        State::ReceivingPollAnswers {
+            poll: opening @ None,
+            ..
+        } => {
+            *opening = Some(Box::new(poll.clone()));
+            true
+        }
+        _ => false,
+    })
+    .await?;
+    if !stored {
+        // Cancelled while the ballot was being sent
+        close_ballot(&bot, &poll).await?;
+        return Ok(true);
+    }
+    if let Some(telegram_poll_id) = telegram_poll_id {
+        loto_polls
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(telegram_poll_id, chat_id);
     }
 
-    let poll = poll.await?;
-    bot.pin_chat_message(msg.chat.id, poll.id).await?;
+    schedule_draw(
+        bot.clone(),
+        dialogue,
+        loto_polls,
+        scheduler,
+        poll_id,
+        deadline,
+    );
+    bot.pin_chat_message(chat_id, poll_id).await?;
 
-    dialogue
-        .update(state.to_receiving_poll_answers(poll))
-        .await?;
+    Ok(true)
+}
+
+async fn send_ballot(
+    bot: &BotType,
+    chat_id: ChatId,
+    thread_id: Option<ThreadId>,
+    question: String,
+    mode: LotoMode,
+    ballot: Ballot,
+    rules: &LotoSettings,
+) -> Result<Message, Box<dyn Error + Send + Sync>> {
+    match ballot {
+        Ballot::Poll => {
+            let mut poll = bot
+                .send_poll(chat_id, question, mode.values().map(|x| x.to_string()))
+                .is_anonymous(false);
+            if let Some(thread_msg_id) = thread_id {
+                poll = poll.message_thread_id(thread_msg_id);
+            }
+            let poll = poll.await?;
+            if poll.poll().is_none() {
+                return Err("Telegram sent back something else than a poll".into());
+            }
+            Ok(poll)
+        }
+        Ballot::Keyboard => {
+            let text = format!("{}\n\n{}", question, describe_bets(&HashMap::new()));
+            let mut message = bot
+                .send_message(chat_id, text)
+                .reply_markup(bet_keyboard(mode, rules));
+            if let Some(thread_msg_id) = thread_id {
+                message = message.message_thread_id(thread_msg_id);
+            }
+            Ok(message.await?)
+        }
+    }
+}
+
+fn format_duration(seconds: i64) -> String {
+    match (seconds / 60, seconds % 60) {
+        (1, 0) => "1 minute".to_string(),
+        (minutes, 0) => format!("{} minutes", minutes),
+        _ => format!("{} secondes", seconds),
+    }
+}
+
+fn describe_rules(rules: &LotoSettings) -> String {
+    format!(
+        "Participation au pot: {}💵, solde minimum pour jouer: {}💵, au moins {} participants",
+        rules.entry_fee, rules.min_balance, rules.min_participants
+    )
+}
+
+fn schedule_draw(
+    bot: BotType,
+    dialogue: DialogueType,
+    loto_polls: LotoPolls,
+    scheduler: &SchedulerType,
+    poll_id: MessageId,
+    deadline: DateTime<Utc>,
+) -> JobHandle {
+    let delay = (deadline - Utc::now()).to_std().unwrap_or_default();
+    scheduler.schedule(dialogue.chat_id(), JobKind::LotoDraw, delay, async move {
+        if let Err(err) = draw_loto(bot, dialogue, loto_polls, poll_id).await {
+            warn!("Failed to draw the lottery: {}", err);
+        }
+    })
+}
+
+pub(crate) async fn resume_lotos(
+    bot: BotType,
+    storage: StorageType,
+    loto_polls: LotoPolls,
+    scheduler: SchedulerType,
+    chat_ids: Vec<ChatId>,
+) {
+    for chat_id in chat_ids {
+        let dialogue = DialogueType::new(storage.clone(), chat_id);
+        let (poll, deadline, closed) = match dialogue.get().await {
+            Ok(Some(State::ReceivingPollAnswers {
+                poll,
+                deadline,
+                closed,
+                ..
+            })) => (poll, deadline, closed),
+            Ok(_) => continue,
+            Err(err) => {
+                warn!("Failed to read the state of chat {}: {}", chat_id, err);
+                continue;
+            }
+        };
+
+        if Utc::now() - deadline > TimeDelta::hours(STALE_LOTO_HOURS) {
+            info!("Cancelling stale lottery in chat {}", chat_id);
+            let announcement =
+                "La loterie a été annulée pendant que je faisais la sieste, personne n'a rien perdu 😴";
+            if let Err(err) = cancel_loto(&bot, &dialogue, &loto_polls, announcement).await {
+                warn!("Failed to cancel the lottery of chat {}: {}", chat_id, err);
+            }
+            continue;
+        }
+
+        // The bot stopped while sending the ballot
+        let Some(poll) = poll else {
+            info!("Cancelling unopened lottery in chat {}", chat_id);
+            let announcement = "La loterie n'a pas pu commencer, personne n'a rien perdu 😴";
+            if let Err(err) = cancel_loto(&bot, &dialogue, &loto_polls, announcement).await {
+                warn!("Failed to cancel the lottery of chat {}: {}", chat_id, err);
+            }
+            continue;
+        };
+
+        // The bot stopped during the draw, the dice may already have been thrown
+        if closed {
+            info!("Cancelling interrupted draw in chat {}", chat_id);
+            let announcement =
+                "Le tirage a été interrompu pendant que je faisais la sieste, la loterie est annulée et personne n'a rien perdu 😴";
+            if let Err(err) = cancel_loto(&bot, &dialogue, &loto_polls, announcement).await {
+                warn!("Failed to cancel the lottery of chat {}: {}", chat_id, err);
+            }
+            continue;
+        }
+
+        info!("Resuming lottery in chat {}", chat_id);
+        if let Some(telegram_poll) = poll.poll() {
+            loto_polls
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .insert(telegram_poll.id.clone(), chat_id);
+        }
+        schedule_draw(
+            bot.clone(),
+            dialogue,
+            loto_polls.clone(),
+            &scheduler,
+            poll.id,
+            deadline,
+        );
+    }
+}
 
-    tokio::spawn(async move {
-        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
-        let _ = draw_loto(bot, dialogue, msg, poll_answers).await;
-    });
+async fn cancel_loto(
+    bot: &BotType,
+    dialogue: &DialogueType,
+    loto_polls: &LotoPolls,
+    announcement: &str,
+) -> HandlerResult {
+    let (poll, closed) = update_state(dialogue, |state| withdraw_loto(state, dialogue.chat_id()))
+        .await?
+        .ok_or("Invalid state")?;
+    loto_polls
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .retain(|_, &mut chat_id| chat_id != dialogue.chat_id());
+
+    let mut message = bot.send_message(dialogue.chat_id(), announcement);
+    if let Some(poll) = poll {
+        // A closed lottery may have closed its ballot already, Telegram refuses to close it twice
+        match close_ballot(bot, &poll).await {
+            Err(err) if closed => info!("Ballot of chat {} already closed: {}", poll.chat.id, err),
+            result => result?,
+        }
+        if let Some(thread_msg_id) = poll.thread_id {
+            message = message.message_thread_id(thread_msg_id);
+        }
+    }
+    message.await?;
+    Ok(())
+}
+
+// Refunds the stakes and goes back to idle, returns the ballot of the cancelled lottery
+// and whether its betting round was closed
+fn withdraw_loto(state: &mut State, chat_id: ChatId) -> Option<(Option<Box<Message>>, bool)> {
+    refund_stakes(state, chat_id);
+    let State::ReceivingPollAnswers { poll, closed, .. } = state else {
+        return None;
+    };
+    let withdrawn = (poll.take(), *closed);
+    *state = std::mem::take(state).into_idle();
+    Some(withdrawn)
+}
 
+// Gives the keyboard stakes back to their players, the lottery will not be drawn
+fn refund_stakes(state: &mut State, chat_id: ChatId) {
+    let State::ReceivingPollAnswers { stakes, .. } = state else {
+        return;
+    };
+    for (player, stake) in std::mem::take(stakes) {
+        if stake != 0 {
+            state.insert(chat_id, &player, stake, Reason::LotoRefund);
+        }
+    }
+}
+
+// Stops the poll or removes the betting keyboard, then unpins it
+async fn close_ballot(bot: &BotType, poll: &Message) -> HandlerResult {
+    if poll.poll().is_some() {
+        bot.stop_poll(poll.chat.id, poll.id).await?;
+    } else {
+        bot.edit_message_reply_markup(poll.chat.id, poll.id).await?;
+    }
+    bot.unpin_chat_message(poll.chat.id)
+        .message_id(poll.id)
+        .await?;
     Ok(())
 }
 
 async fn draw_loto(
     bot: BotType,
     dialogue: DialogueType,
-    msg: Message,
-    poll_answers: Arc<Mutex<HashMap<UserId, u8>>>,
+    loto_polls: LotoPolls,
+    poll_id: MessageId,
 ) -> HandlerResult {
-    let mut state = dialogue.get().await?.ok_or("No state")?;
+    let (poll, rules, mode) = match dialogue.get().await?.ok_or("No state")? {
+        State::ReceivingPollAnswers {
+            poll: Some(poll),
+            rules,
+            mode,
+            ..
+        } if poll.id == poll_id => (poll, rules, mode),
+        _ => return Err("The lottery to draw is no longer running".into()),
+    };
 
-    let dice_value = draw_die(&bot, &msg).await?;
+    let (bets, stakes) = close_bets(&bot, &dialogue, &poll, &loto_polls).await?;
+    if bets.len() < rules.min_participants {
+        let cancelled = update_state(&dialogue, |state| {
+            if !is_drawing(state, poll_id) {
+                return false;
+            }
+            refund_stakes(state, dialogue.chat_id());
+            *state = std::mem::take(state).into_idle();
+            true
+        })
+        .await?;
+        if !cancelled {
+            return Err("The lottery was cancelled during the draw".into());
+        }
 
-    let (winner_ids, looser_ids, mut winners) =
-        get_poll_winners(&state, &bot, &msg, poll_answers, dice_value).await?;
+        let announcement = format!(
+            "Loterie annulée faute de joueurs: {} participant(s) sur les {} nécessaires, personne n'a rien payé 🤷",
+            bets.len(),
+            rules.min_participants
+        );
+        let mut message = bot.send_message(poll.chat.id, announcement);
+        if let Some(thread_msg_id) = poll.thread_id {
+            message = message.message_thread_id(thread_msg_id);
+        }
+        message.await?;
+        return Ok(());
+    }
+    let dice_value = draw_dice(&bot, &poll, mode).await?;
+    let (winner_ids, looser_ids) = get_winner_ids(&bets, dice_value);
 
     tokio::time::sleep(std::time::Duration::from_secs(4)).await;
 
-    let mut bankrupts = vec![];
-
-    for winner_id in &winner_ids {
-        if state.get(winner_id) < &10 {
-            let username = winners.remove(winner_ids.iter().position(|x| x == winner_id).unwrap());
-            bankrupts.push(username);
-            continue;
+    let settlement = update_state(&dialogue, |state| {
+        if !is_drawing(state, poll_id) {
+            return None;
         }
-        state.insert(winner_id, 50);
-    }
+        // Poll voters pay the entry fee now if they can afford it, keyboard stakes are already paid
+        let threshold = rules.min_balance.max(rules.entry_fee);
+        let stake = |state: &State, player: &UserId| match stakes.get(player) {
+            Some(&stake) => Some(stake),
+            None if state.get(player) >= &threshold => Some(rules.entry_fee),
+            None => None,
+        };
+        let (winners, bankrupt_ids): (Vec<_>, Vec<_>) = winner_ids
+            .into_iter()
+            .map(|winner_id| (winner_id, stake(state, &winner_id)))
+            .partition(|(_, stake)| stake.is_some());
+        let winners = winners
+            .into_iter()
+            .filter_map(|(winner_id, stake)| Some((winner_id, stake?)))
+            .collect::<Vec<_>>();
+        let bankrupt_ids = bankrupt_ids
+            .into_iter()
+            .map(|(bankrupt_id, _)| bankrupt_id)
+            .collect::<Vec<_>>();
+        let loosers = looser_ids
+            .into_iter()
+            .filter_map(|looser_id| Some((looser_id, stake(state, &looser_id)?)))
+            .collect::<Vec<_>>();
 
-    for looser_id in &looser_ids {
-        if state.get(looser_id) < &10 {
-            continue;
+        let rollover = state.jackpot();
+        let total = rollover
+            + winners
+                .iter()
+                .chain(&loosers)
+                .map(|(_, stake)| stake)
+                .sum::<i64>();
+        // A single die splits the pot between the winners in proportion to their stakes,
+        // two dice pay each winner by the odds of their sum
+        let winning_stakes = winners.iter().map(|(_, stake)| stake).sum::<i64>();
+        let mut paid = 0;
+        let mut shares = vec![];
+        for &(winner_id, stake) in &winners {
+            let share = match (mode.odds_payout(stake, dice_value), winning_stakes) {
+                (Some(payout), _) => payout,
+                (None, 0) => total / winners.len() as i64,
+                (None, _) => total * stake / winning_stakes,
+            };
+            let already_paid = if stakes.contains_key(&winner_id) {
+                stake
+            } else {
+                0
+            };
+            state.insert(
+                poll.chat.id,
+                &winner_id,
+                share - stake + already_paid,
+                Reason::LotoWin,
+            );
+            paid += share;
+            shares.push((winner_id, share));
+        }
+        for &(looser_id, stake) in &loosers {
+            if stake == 0 || stakes.contains_key(&looser_id) {
+                continue;
+            }
+            state.insert(poll.chat.id, &looser_id, -stake, Reason::LotoLoss);
         }
-        state.insert(looser_id, -10);
-    }
 
-    dialogue.update(state.to_idle()).await?;
+        // What nobody won, including the remainder of an uneven split, rolls over to the next lottery.
+        // Fixed odds can pay more than the pot, the bank covers the difference.
+        state.set_jackpot((total - paid).max(0));
+        let entries = winners
+            .iter()
+            .zip(&shares)
+            .map(|(&(player, stake), &(_, payout))| (player, stake, payout))
+            .chain(loosers.iter().map(|&(player, stake)| (player, stake, 0)))
+            .map(|(player, stake, payout)| LotoEntry {
+                player,
+                bet: bets[&player],
+                stake,
+                payout,
+            })
+            .collect();
+        state.loto_history_mut().record(LotoDraw {
+            timestamp: Utc::now(),
+            mode,
+            value: dice_value,
+            pot: total,
+            rollover,
+            entries,
+            bankrupts: bankrupt_ids.clone(),
+        });
+        *state = std::mem::take(state).into_idle();
+        Some((shares, bankrupt_ids, Pot { total, rollover }))
+    })
+    .await?;
+    let (winners, bankrupt_ids, pot) =
+        settlement.ok_or("The lottery was cancelled during the draw")?;
 
-    announce_winners(winners, bankrupts, bot, msg).await?;
+    let mut winner_names = vec![];
+    for (winner_id, share) in winners {
+        let winner = get_username(&bot, poll.chat.id, &winner_id)
+            .await
+            .unwrap_or("____".to_string());
+        winner_names.push((winner, share));
+    }
+    let bankrupts = get_usernames(&bot, &poll.chat.id, &bankrupt_ids).await;
+    announce_winners(winner_names, bankrupts, pot, bot, &poll).await?;
 
     Ok(())
 }
 
+struct Pot {
+    total: i64,
+    // Left over by the previous lotteries
+    rollover: i64,
+}
+
 async fn announce_winners(
-    winners: Vec<String>,
+    winners: Vec<(String, i64)>,
     bankrupts: Vec<String>,
+    pot: Pot,
     bot: Throttle<Bot>,
-    msg: Message,
+    msg: &Message,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut content = match &*winners {
-        [] => "Et les gagnants sont 🥁🥁🥁...  personne 😢".to_string(),
-        [winner] => format!("Et le gagnant est 🥁🥁🥁... {}", winner),
-        [first_winner, others @ ..] => format!(
-            "Et les gagnants sont 🥁🥁🥁... {} et {}",
-            others.join(", "),
-            first_winner
+    let mut content = match pot.rollover {
+        0 => format!("Le pot est de {}💵\n\n", pot.total),
+        rollover => format!(
+            "Le pot est de {}💵 dont {}💵 de cagnotte reportée\n\n",
+            pot.total, rollover
+        ),
+    };
+    content += &match &*winners {
+        [] => format!(
+            "Et les gagnants sont 🥁🥁🥁...  personne 😢 Les {}💵 sont reportés à la prochaine loterie",
+            pot.total
+        ),
+        [(winner, share)] => format!(
+            "Et le gagnant est 🥁🥁🥁... {} qui remporte {}💵",
+            winner, share
+        ),
+        [(first_winner, first_share), others @ ..] => format!(
+            "Et les gagnants sont 🥁🥁🥁... {} et {} ({}💵)",
+            others
+                .iter()
+                .map(|(winner, share)| format!("{} ({}💵)", winner, share))
+                .collect::<Vec<_>>()
+                .join(", "),
+            first_winner,
+            first_share
         ),
     };
 
@@ -135,58 +719,85 @@ async fn announce_winners(
     Ok(())
 }
 
-async fn get_poll_winners(
-    state: &State,
-    bot: &Throttle<Bot>,
-    msg: &Message,
-    poll_answers: Arc<Mutex<HashMap<UserId, u8>>>,
-    dice_value: u8,
-) -> Result<(Vec<UserId>, Vec<UserId>, Vec<String>), Box<dyn Error + Send + Sync>> {
-    let poll = match *state {
-        State::ReceivingPollAnswers { ref poll, .. } => poll,
-        _ => return Err("Invalid state".into()),
-    };
-    bot.stop_poll(msg.chat.id, poll.id).await?;
-    bot.unpin_chat_message(msg.chat.id)
-        .message_id(poll.id)
-        .await?;
-    let (winner_ids, looser_ids) = get_winner_ids(
-        &poll_answers
+// Whether the lottery of this poll is closed and waiting for its settlement
+fn is_drawing(state: &State, poll_id: MessageId) -> bool {
+    matches!(
+        state,
+        State::ReceivingPollAnswers {
+            poll: Some(poll),
+            closed: true,
+            ..
+        } if poll.id == poll_id
+    )
+}
+
+// Closes the betting round, the bets can no longer change once it returns
+async fn close_bets(
+    bot: &BotType,
+    dialogue: &DialogueType,
+    poll: &Message,
+    loto_polls: &LotoPolls,
+) -> Result<(HashMap<UserId, u8>, HashMap<UserId, i64>), Box<dyn Error + Send + Sync>> {
+    // Under the lock, so that a late vote or button press cannot slip in after the snapshot
+    let bets = update_state(dialogue, |state| match state {
+        State::ReceivingPollAnswers {
+            poll: Some(current),
+            bets,
+            stakes,
+            closed,
+            ..
+        } if current.id == poll.id => {
+            *closed = true;
+            Some((bets.clone(), stakes.clone()))
+        }
+        _ => None,
+    })
+    .await?
+    .ok_or("Invalid state")?;
+
+    close_ballot(bot, poll).await?;
+    if let Some(poll) = poll.poll() {
+        loto_polls
             .lock()
             .unwrap_or_else(|err| err.into_inner())
-            .to_owned(),
-        dice_value,
-    );
-    let winners = get_usernames(bot, &msg.chat.id, &winner_ids).await;
-    Ok((winner_ids, looser_ids, winners))
+            .remove(&poll.id);
+    }
+    Ok(bets)
 }
 
-async fn draw_die(
+async fn draw_dice(
     bot: &teloxide::adaptors::Throttle<teloxide::Bot>,
     msg: &Message,
+    mode: LotoMode,
 ) -> Result<u8, Box<dyn Error + Send + Sync>> {
     let mut message = bot.send_message(
         msg.chat.id,
         "Les paris sont fermés. C'est l'heure du lancer...",
     );
-    let mut dice = bot.send_dice(msg.chat.id);
     if let Some(thread_msg_id) = msg.thread_id {
         message = message.message_thread_id(thread_msg_id);
-        dice = dice.message_thread_id(thread_msg_id);
     }
     message.await?;
-    let dice = dice.await?;
-    let dice_value = match dice.kind {
-        MessageKind::Dice(MessageDice {
-            dice:
-                Dice {
-                    emoji: DiceEmoji::Dice,
-                    value,
-                },
-        }) => value,
-        _ => return Err("How the fuck did telegram turn a dice into something else ?".into()),
-    };
-    Ok(dice_value)
+
+    let mut total = 0;
+    for _ in 0..mode.dice_count() {
+        let mut dice = bot.send_dice(msg.chat.id);
+        if let Some(thread_msg_id) = msg.thread_id {
+            dice = dice.message_thread_id(thread_msg_id);
+        }
+        let dice = dice.await?;
+        total += match dice.kind {
+            MessageKind::Dice(MessageDice {
+                dice:
+                    Dice {
+                        emoji: DiceEmoji::Dice,
+                        value,
+                    },
+            }) => value,
+            _ => return Err("How the fuck did telegram turn a dice into something else ?".into()),
+        };
+    }
+    Ok(total)
 }
 
 fn get_winner_ids(
@@ -207,31 +818,205 @@ fn get_winner_ids(
 }
 
 pub(crate) async fn register_answer(
-    _bot: BotType,
-    poll_answers: Arc<Mutex<HashMap<UserId, u8>>>,
+    storage: StorageType,
+    loto_polls: LotoPolls,
     pa: PollAnswer,
 ) -> HandlerResult {
-    match pa {
-        PollAnswer {
-            option_ids,
-            voter,
-            poll_id: _,
-        } => {
-            let mut poll_answers = poll_answers.lock().unwrap_or_else(|err| err.into_inner());
-            let voter = voter.user().ok_or("Voter vanished from channel")?;
-            if let Some(option_id) = option_ids.first() {
-                poll_answers.insert(voter.id, *option_id + 1);
-            } else {
-                // Remove the user's answer if they removed their vote
-                poll_answers.remove(&voter.id);
-            }
-        }
+    let PollAnswer {
+        option_ids,
+        voter,
+        poll_id,
+    } = pa;
+    let chat_id = loto_polls
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get(&poll_id)
+        .copied();
+    let Some(chat_id) = chat_id else {
+        return Ok(()); // Not one of our running lotteries
     };
+
+    let voter = voter.user().ok_or("Voter vanished from channel")?.id;
+    let dialogue = DialogueType::new(storage, chat_id);
+    update_state(&dialogue, |state| {
+        let (bets, mode) = match state {
+            State::ReceivingPollAnswers {
+                poll: Some(poll),
+                bets,
+                mode,
+                closed: false,
+                ..
+            } if poll.poll().is_some_and(|poll| poll.id == poll_id) => (bets, mode),
+            _ => return,
+        };
+        if let Some(option_id) = option_ids.first() {
+            bets.insert(voter, mode.values().start() + *option_id);
+        } else {
+            // Remove the user's answer if they removed their vote
+            bets.remove(&voter);
+        }
+    })
+    .await?;
     Ok(())
 }
 
-pub(crate) async fn reset_roll(dialogue: DialogueType) -> HandlerResult {
-    let state = dialogue.get().await?.ok_or("No state")?;
-    dialogue.update(state.to_idle()).await?;
-    Ok(())
+pub(crate) async fn cancel_roll(
+    bot: BotType,
+    dialogue: DialogueType,
+    loto_polls: LotoPolls,
+    scheduler: SchedulerType,
+    msg: Message,
+) -> HandlerResult {
+    let deadline = match dialogue.get().await?.ok_or("No state")? {
+        State::ReceivingPollAnswers { deadline, .. } => deadline,
+        _ => return reply_to(&bot, &msg, "Il n'y a pas de loterie en cours").await,
+    };
+    let cancelled = scheduler.cancel(dialogue.chat_id(), JobKind::LotoDraw);
+    // Without a pending job the draw is under way, unless it failed a while ago
+    if cancelled.is_empty() && Utc::now() - deadline < TimeDelta::minutes(1) {
+        return reply_to(&bot, &msg, "Trop tard, le tirage est en cours").await;
+    }
+
+    let admin = msg
+        .from
+        .as_ref()
+        .map(|admin| admin.username.clone().unwrap_or(admin.first_name.clone()))
+        .unwrap_or_default();
+    let announcement = format!(
+        "Loterie annulée par @{}, les mises ont été remboursées et personne n'a rien perdu 💸",
+        admin
+    );
+    cancel_loto(&bot, &dialogue, &loto_polls, &announcement).await
+}
+
+pub(crate) async fn set_loto_settings(
+    bot: BotType,
+    dialogue: DialogueType,
+    msg: Message,
+    args: String,
+) -> HandlerResult {
+    const USAGE: &str =
+        "Utilisation: /setloto <participation> <solde minimum> [nombre minimum de participants]";
+
+    let amounts = args
+        .split_whitespace()
+        .map(|amount| match amount.parse::<i64>() {
+            Ok(amount) if (0..=MAX_LOTO_AMOUNT).contains(&amount) => Ok(amount),
+            _ => Err(format!(
+                "{:?} n'est pas un montant valide, il doit être entre 0 et {}💵",
+                amount, MAX_LOTO_AMOUNT
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>();
+    // The minimum number of participants is kept when omitted
+    let rules = match amounts.as_deref() {
+        Ok([]) => None,
+        Ok(&[entry_fee, min_balance]) => Some((entry_fee, min_balance, None)),
+        Ok(&[entry_fee, min_balance, min_participants]) if min_participants > 0 => {
+            Some((entry_fee, min_balance, Some(min_participants as usize)))
+        }
+        Ok(_) => return reply_to(&bot, &msg, USAGE).await,
+        Err(err) => return reply_to(&bot, &msg, format!("{}\n{}", err, USAGE)).await,
+    };
+
+    let rules = update_state(&dialogue, |state| {
+        let loto = &mut state.settings_mut().loto;
+        if let Some((entry_fee, min_balance, min_participants)) = rules {
+            loto.entry_fee = entry_fee;
+            loto.min_balance = min_balance;
+            if let Some(min_participants) = min_participants {
+                loto.min_participants = min_participants;
+            }
+        }
+        loto.clone()
+    })
+    .await?;
+
+    reply_to(
+        &bot,
+        &msg,
+        format!("Règles de la loterie: {}", describe_rules(&rules)),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pays_sums_by_their_odds() {
+        assert_eq!(LotoMode::Sum.odds_payout(10, 2), Some(360));
+        assert_eq!(LotoMode::Sum.odds_payout(10, 6), Some(72));
+        assert_eq!(LotoMode::Sum.odds_payout(10, 7), Some(60));
+        assert_eq!(LotoMode::Sum.odds_payout(10, 12), Some(360));
+        assert_eq!(LotoMode::Sum.odds_payout(0, 7), Some(0));
+        assert_eq!(LotoMode::Single.odds_payout(10, 3), None);
+    }
+
+    #[test]
+    fn rare_sums_pay_more() {
+        let payouts = LotoMode::Sum
+            .values()
+            .map(|value| LotoMode::Sum.odds_payout(36, value).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            payouts,
+            [1296, 648, 432, 324, 259, 216, 259, 324, 432, 648, 1296]
+        );
+    }
+
+    #[test]
+    fn cancelling_keeps_the_chat_state() {
+        let chat = ChatId(1);
+        let (player, bettor) = (UserId(1), UserId(2));
+        let mut state = State::default();
+        state.insert(chat, &player, 500, Reason::Give);
+        state.set_jackpot(42);
+        let mut state = state.into_receiving_poll_answers(Utc::now(), LotoMode::Single);
+        state.insert(chat, &bettor, -30, Reason::LotoBet);
+        if let State::ReceivingPollAnswers { stakes, .. } = &mut state {
+            stakes.insert(bettor, 30);
+        }
+
+        assert!(matches!(
+            withdraw_loto(&mut state, chat),
+            Some((None, false))
+        ));
+        assert!(matches!(state, State::Idle { .. }));
+        assert_eq!(*state.get(&player), 600);
+        assert_eq!(*state.get(&bettor), 100);
+        assert_eq!(state.jackpot(), 42);
+        let reasons = state
+            .history(&bettor, 10)
+            .iter()
+            .map(|transaction| transaction.reason)
+            .collect::<Vec<_>>();
+        assert_eq!(reasons, [Reason::LotoRefund, Reason::LotoBet]);
+        assert!(withdraw_loto(&mut state, chat).is_none());
+    }
+
+    #[test]
+    fn parses_roll_arguments() {
+        let round: LotoRound = "".parse().unwrap();
+        assert_eq!(
+            (round.duration, round.mode, round.ballot),
+            (60, LotoMode::Single, Ballot::Poll)
+        );
+        let round: LotoRound = "120 somme clavier".parse().unwrap();
+        assert_eq!(
+            (round.duration, round.mode, round.ballot),
+            (120, LotoMode::Sum, Ballot::Keyboard)
+        );
+        let round: LotoRound = "KEYBOARD sum 3600".parse().unwrap();
+        assert_eq!(
+            (round.duration, round.mode, round.ballot),
+            (3600, LotoMode::Sum, Ballot::Keyboard)
+        );
+
+        assert!("9".parse::<LotoRound>().is_err());
+        assert!("3601".parse::<LotoRound>().is_err());
+        assert!("-60".parse::<LotoRound>().is_err());
+        assert!("60 triple".parse::<LotoRound>().is_err());
+    }
 }
@@ -0,0 +1,264 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Utc};
+use teloxide::types::{Message, UserId};
+
+use crate::ledger::format_elapsed;
+use crate::loto::LotoMode;
+use crate::utils::{get_username, reply_to, BotType, DialogueType, HandlerResult};
+
+const LOTO_HISTORY_LENGTH: usize = 5;
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct LotoEntry {
+    pub(crate) player: UserId,
+    pub(crate) bet: u8,
+    pub(crate) stake: i64,
+    // Taken from the pot, zero for the losers
+    pub(crate) payout: i64,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct LotoDraw {
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) mode: LotoMode,
+    pub(crate) value: u8,
+    pub(crate) pot: i64,
+    pub(crate) rollover: i64,
+    pub(crate) entries: Vec<LotoEntry>,
+    // Guessed right but could not afford the entry fee
+    pub(crate) bankrupts: Vec<UserId>,
+}
+
+impl LotoDraw {
+    fn winners(&self) -> impl Iterator<Item = &LotoEntry> {
+        self.entries.iter().filter(|entry| entry.bet == self.value)
+    }
+}
+
+// Draws kept in full, older ones only count in the statistics
+const KEPT_DRAWS: usize = 50;
+
+// Append-only like the ledger, and capped like it
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(from = "StoredLotoHistory", into = "StoredLotoHistory")]
+pub(crate) struct LotoHistory {
+    draws: VecDeque<LotoDraw>,
+    draw_count: usize,
+    drawn: HashMap<(LotoMode, u8), usize>,
+    wins: HashMap<UserId, usize>,
+}
+
+// JSON keys must be strings, the statistics are saved as lists
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredLotoHistory {
+    draws: VecDeque<LotoDraw>,
+    draw_count: usize,
+    drawn: Vec<(LotoMode, u8, usize)>,
+    wins: Vec<(UserId, usize)>,
+}
+
+impl From<StoredLotoHistory> for LotoHistory {
+    fn from(stored: StoredLotoHistory) -> Self {
+        Self {
+            draws: stored.draws,
+            draw_count: stored.draw_count,
+            drawn: stored
+                .drawn
+                .into_iter()
+                .map(|(mode, value, count)| ((mode, value), count))
+                .collect(),
+            wins: stored.wins.into_iter().collect(),
+        }
+    }
+}
+
+impl From<LotoHistory> for StoredLotoHistory {
+    fn from(history: LotoHistory) -> Self {
+        Self {
+            draws: history.draws,
+            draw_count: history.draw_count,
+            drawn: history
+                .drawn
+                .into_iter()
+                .map(|((mode, value), count)| (mode, value, count))
+                .collect(),
+            wins: history.wins.into_iter().collect(),
+        }
+    }
+}
+
+impl LotoHistory {
+    pub(crate) fn record(&mut self, draw: LotoDraw) {
+        self.draw_count += 1;
+        *self.drawn.entry((draw.mode, draw.value)).or_default() += 1;
+        for entry in draw.winners() {
+            *self.wins.entry(entry.player).or_default() += 1;
+        }
+        self.draws.push_back(draw);
+        if self.draws.len() > KEPT_DRAWS {
+            self.draws.pop_front();
+        }
+    }
+
+    // Newest first
+    fn last(&self, count: usize) -> impl Iterator<Item = &LotoDraw> {
+        self.draws.iter().rev().take(count)
+    }
+
+    fn most_drawn(&self, mode: LotoMode) -> Option<(u8, usize)> {
+        self.drawn
+            .iter()
+            .filter(|((drawn_mode, _), _)| *drawn_mode == mode)
+            .map(|(&(_, value), &count)| (value, count))
+            .max_by_key(|&(value, count)| (count, std::cmp::Reverse(value)))
+    }
+
+    fn win_counts(&self) -> Vec<(UserId, usize)> {
+        let mut counts = self
+            .wins
+            .iter()
+            .map(|(&player, &count)| (player, count))
+            .collect::<Vec<_>>();
+        counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        counts
+    }
+}
+
+pub(crate) async fn loto_history(
+    bot: BotType,
+    dialogue: DialogueType,
+    msg: Message,
+) -> HandlerResult {
+    let state = dialogue.get().await?.ok_or("No state")?;
+    let history = state.loto_history();
+    if history.draw_count == 0 {
+        return reply_to(&bot, &msg, "Aucune loterie tirée pour l'instant").await;
+    }
+
+    let draws = history.last(LOTO_HISTORY_LENGTH).collect::<Vec<_>>();
+    let win_counts = history.win_counts();
+    let mut usernames = HashMap::new();
+    let mut players = vec![];
+    for draw in &draws {
+        players.extend(draw.entries.iter().map(|entry| entry.player));
+        players.extend(&draw.bankrupts);
+    }
+    players.extend(win_counts.iter().take(10).map(|&(player, _)| player));
+    players.sort_by_key(|player| player.0);
+    players.dedup();
+    for player in players {
+        let username = get_username(&bot, msg.chat.id, &player)
+            .await
+            .unwrap_or("____".to_string());
+        usernames.insert(player, username);
+    }
+    let username = |player: &UserId| usernames.get(player).cloned().unwrap_or_default();
+
+    let mut message = "Derniers tirages:\n".to_string();
+    for draw in draws {
+        let die = match draw.mode {
+            LotoMode::Single => "🎲",
+            LotoMode::Sum => "🎲🎲",
+        };
+        message.push_str(&format!(
+            "{} {} ({}): pot de {}💵, {} joueur(s)\n",
+            die,
+            draw.value,
+            format_elapsed(&draw.timestamp),
+            draw.pot,
+            draw.entries.len()
+        ));
+        for entry in &draw.entries {
+            let outcome = match entry.bet == draw.value {
+                true => format!("🏆 {}💵", entry.payout),
+                false => "❌".to_string(),
+            };
+            message.push_str(&format!(
+                "  {}: {}💵 sur le {} → {}\n",
+                username(&entry.player),
+                entry.stake,
+                entry.bet,
+                outcome
+            ));
+        }
+        for bankrupt in &draw.bankrupts {
+            message.push_str(&format!(
+                "  {}: trop fauché pour gagner\n",
+                username(bankrupt)
+            ));
+        }
+    }
+
+    message.push_str(&format!(
+        "\nStatistiques sur {} tirages:\n",
+        history.draw_count
+    ));
+    if let Some((value, count)) = history.most_drawn(LotoMode::Single) {
+        message.push_str(&format!(
+            "Numéro le plus tiré: {} ({} fois)\n",
+            value, count
+        ));
+    }
+    if let Some((value, count)) = history.most_drawn(LotoMode::Sum) {
+        message.push_str(&format!(
+            "Somme la plus tirée: {} ({} fois)\n",
+            value, count
+        ));
+    }
+    if !win_counts.is_empty() {
+        let mut wins = vec![];
+        for (player, count) in win_counts.iter().take(10) {
+            wins.push(format!("{} {}", username(player), count));
+        }
+        message.push_str(&format!("Victoires: {}\n", wins.join(", ")));
+    }
+
+    reply_to(&bot, &msg, message).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(value: u8, player: u64, bet: u8) -> LotoDraw {
+        LotoDraw {
+            timestamp: Utc::now(),
+            mode: LotoMode::Single,
+            value,
+            pot: 10,
+            rollover: 0,
+            entries: vec![LotoEntry {
+                player: UserId(player),
+                bet,
+                stake: 10,
+                payout: if bet == value { 10 } else { 0 },
+            }],
+            bankrupts: vec![],
+        }
+    }
+
+    #[test]
+    fn keeps_the_statistics_of_dropped_draws() {
+        let mut history = LotoHistory::default();
+        for n in 0..KEPT_DRAWS + 5 {
+            history.record(draw(3, n as u64 % 2, 3));
+        }
+
+        assert_eq!(history.draws.len(), KEPT_DRAWS);
+        assert_eq!(history.draw_count, KEPT_DRAWS + 5);
+        assert_eq!(
+            history.most_drawn(LotoMode::Single),
+            Some((3, KEPT_DRAWS + 5))
+        );
+        assert_eq!(history.most_drawn(LotoMode::Sum), None);
+
+        let saved = serde_json::to_string(&history).unwrap();
+        let reloaded: LotoHistory = serde_json::from_str(&saved).unwrap();
+        assert_eq!(reloaded.draw_count, history.draw_count);
+        assert_eq!(reloaded.draws.len(), KEPT_DRAWS);
+        let mut wins = reloaded.win_counts();
+        wins.sort_by_key(|(player, _)| player.0);
+        assert_eq!(wins, [(UserId(0), 28), (UserId(1), 27)]);
+    }
+}
@@ -0,0 +1,210 @@
+use std::{collections::HashMap, str::FromStr};
+
+use log::warn;
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters},
+    requests::Requester,
+    types::{
+        CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message, MessageId,
+        UserId,
+    },
+};
+
+use crate::ledger::Reason;
+use crate::loto::LotoMode;
+use crate::state::{LotoSettings, State};
+use crate::utils::{update_state, BotType, DialogueType, HandlerResult, StorageType};
+
+const STAKE_MULTIPLIERS: [i64; 4] = [1, 2, 5, 10];
+
+enum BetAction {
+    Number(u8),
+    Stake(i64),
+    Withdraw,
+}
+
+impl FromStr for BetAction {
+    type Err = ();
+
+    fn from_str(data: &str) -> Result<Self, Self::Err> {
+        match data.strip_prefix("loto:").ok_or(())?.split_once(':') {
+            Some(("number", number)) => number.parse().map(Self::Number).map_err(|_| ()),
+            Some(("stake", stake)) => stake.parse().map(Self::Stake).map_err(|_| ()),
+            None if data == "loto:withdraw" => Ok(Self::Withdraw),
+            _ => Err(()),
+        }
+    }
+}
+
+fn stake_options(rules: &LotoSettings) -> impl Iterator<Item = i64> {
+    let unit = rules.entry_fee.max(1);
+    STAKE_MULTIPLIERS
+        .into_iter()
+        .map(move |factor| factor * unit)
+}
+
+pub(crate) fn bet_keyboard(mode: LotoMode, rules: &LotoSettings) -> InlineKeyboardMarkup {
+    let numbers = mode
+        .values()
+        .map(|value| {
+            InlineKeyboardButton::callback(value.to_string(), format!("loto:number:{}", value))
+        })
+        .collect::<Vec<_>>();
+    let stakes = stake_options(rules)
+        .map(|stake| {
+            InlineKeyboardButton::callback(format!("{}💵", stake), format!("loto:stake:{}", stake))
+        })
+        .collect::<Vec<_>>();
+
+    let mut rows = numbers.chunks(6).map(<[_]>::to_vec).collect::<Vec<_>>();
+    rows.push(stakes);
+    rows.push(vec![InlineKeyboardButton::callback(
+        "Retirer mon pari",
+        "loto:withdraw",
+    )]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+pub(crate) fn describe_bets(stakes: &HashMap<UserId, i64>) -> String {
+    match stakes.len() {
+        0 => "🎟️ Aucun pari pour l'instant".to_string(),
+        1 => format!("🎟️ 1 pari, {}💵 en jeu", stakes.values().sum::<i64>()),
+        bets => format!(
+            "🎟️ {} paris, {}💵 en jeu",
+            bets,
+            stakes.values().sum::<i64>()
+        ),
+    }
+}
+
+// Pays or refunds the difference with the player's previous stake, returns the text of the updated message
+fn place_bet(
+    state: &mut State,
+    chat_id: ChatId,
+    message_id: MessageId,
+    player: UserId,
+    action: BetAction,
+) -> Result<(String, Option<String>), String> {
+    let State::ReceivingPollAnswers {
+        poll,
+        bets,
+        stakes,
+        rules,
+        mode,
+        closed,
+        ..
+    } = state
+    else {
+        return Err("Cette loterie est terminée".to_string());
+    };
+    let Some(poll) = poll.as_ref().filter(|poll| poll.id == message_id) else {
+        return Err("Cette loterie est terminée".to_string());
+    };
+    if *closed {
+        return Err("Les paris sont fermés".to_string());
+    }
+    let previous_bets = describe_bets(stakes);
+    let question = poll.text().and_then(|text| text.rsplit_once("\n\n"));
+    let question = question.map(|(question, _)| question.to_string());
+    let rules = rules.clone();
+    let bet = bets.get(&player).copied();
+    let stake = stakes.get(&player).copied();
+
+    let (new_bet, new_stake, answer) = match action {
+        BetAction::Number(number) if mode.values().contains(&number) => {
+            let stake = stake.unwrap_or(rules.entry_fee);
+            let answer = format!("Tu paries {}💵 sur le {}", stake, number);
+            (Some(number), stake, answer)
+        }
+        BetAction::Stake(stake) if stake_options(&rules).any(|option| option == stake) => {
+            let Some(bet) = bet else {
+                return Err("Choisis d'abord un numéro".to_string());
+            };
+            let answer = format!("Tu paries {}💵 sur le {}", stake, bet);
+            (Some(bet), stake, answer)
+        }
+        BetAction::Withdraw if bet.is_some() => (None, 0, "Pari retiré".to_string()),
+        BetAction::Withdraw => return Err("Tu n'as pas parié".to_string()),
+        _ => return Err("Ce pari n'est pas possible".to_string()),
+    };
+
+    let delta = stake.unwrap_or(0) - new_stake;
+    let balance = *state.get(&player);
+    if delta < 0 && (balance + delta < 0 || balance < rules.min_balance) {
+        return Err(format!(
+            "Tu n'as que {}💵, il faut au moins {}💵 pour miser {}💵",
+            balance,
+            rules.min_balance.max(-delta),
+            new_stake
+        ));
+    }
+    match delta {
+        0 => {}
+        _ if delta < 0 => state.insert(chat_id, &player, delta, Reason::LotoBet),
+        _ => state.insert(chat_id, &player, delta, Reason::LotoRefund),
+    }
+
+    let State::ReceivingPollAnswers { bets, stakes, .. } = state else {
+        unreachable!("The state was checked above");
+    };
+    match new_bet {
+        Some(new_bet) => {
+            bets.insert(player, new_bet);
+            stakes.insert(player, new_stake);
+        }
+        None => {
+            bets.remove(&player);
+            stakes.remove(&player);
+        }
+    }
+
+    let bets = describe_bets(stakes);
+    let text = question
+        .filter(|_| bets != previous_bets)
+        .map(|question| format!("{}\n\n{}", question, bets));
+    Ok((answer, text))
+}
+
+pub(crate) async fn register_bet(
+    bot: BotType,
+    storage: StorageType,
+    query: CallbackQuery,
+) -> HandlerResult {
+    let Some(action) = query.data.as_deref().and_then(|data| data.parse().ok()) else {
+        return Ok(()); // Not one of our buttons
+    };
+    let Some(message) = &query.message else {
+        return Ok(());
+    };
+    let (chat_id, message_id) = (message.chat().id, message.id());
+
+    let dialogue = DialogueType::new(storage, chat_id);
+    let outcome = update_state(&dialogue, |state| {
+        place_bet(state, chat_id, message_id, query.from.id, action)
+    })
+    .await?;
+
+    let (answer, text, failed) = match outcome {
+        Ok((answer, text)) => (answer, text, false),
+        Err(err) => (err, None, true),
+    };
+    bot.answer_callback_query(query.id.clone())
+        .text(answer)
+        .show_alert(failed)
+        .await?;
+
+    if let Some(text) = text {
+        let keyboard = query
+            .regular_message()
+            .and_then(Message::reply_markup)
+            .cloned();
+        let mut edit = bot.edit_message_text(chat_id, message_id, text);
+        if let Some(keyboard) = keyboard {
+            edit = edit.reply_markup(keyboard);
+        }
+        if let Err(err) = edit.await {
+            warn!("Failed to update the lottery bets: {}", err);
+        }
+    }
+    Ok(())
+}
@@ -2,10 +2,21 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::sync::{Arc, Mutex};
 
-use commands::{balance, give_money, help, leaderboard};
-use emoji_games::emoji_games_handler;
+use bailout::{bailout, set_bailout};
+use commands::{balance, give_money, help, history, leaderboard, pay};
+use daily::{daily, set_daily};
+use emoji_games::{
+    emoji_games_handler, set_default_stake, set_dice_call, set_payout, set_stake, show_payouts,
+    toggle_practice_mode,
+};
 use log::{info, warn};
-use loto::{register_answer, reset_roll, start_loto};
+use loto::{cancel_roll, register_answer, resume_lotos, set_loto_settings, start_loto, LotoPolls};
+use loto_history::loto_history;
+use loto_keyboard::register_bet;
+use payouts::{PayoutTables, PayoutsType};
+use permissions::{is_authorized, permission_denied, Permissions, PermissionsType};
+use recurring::{resume_recurring_lotos, schedule_loto, unschedule_loto};
+use scheduler::{Scheduler, SchedulerType};
 use state::State;
 use teloxide::adaptors::throttle::Limits;
 use teloxide::dispatching::dialogue::serializer::Json;
@@ -14,11 +25,20 @@ use teloxide::dispatching::UpdateHandler;
 use teloxide::prelude::*;
 use teloxide::types::MessageKind;
 use teloxide::utils::command::BotCommands;
-use utils::{BotType, DialogueType, HandlerResult};
+use utils::{get_stored_chat_ids, BotType, DialogueType, HandlerResult, StorageType};
 
+mod bailout;
 mod commands;
+mod daily;
 mod emoji_games;
+mod ledger;
 mod loto;
+mod loto_history;
+mod loto_keyboard;
+mod payouts;
+mod permissions;
+mod recurring;
+mod scheduler;
 mod state;
 mod utils;
 
@@ -30,16 +50,97 @@ mod utils;
 enum Command {
     #[command(description = "Affiche ce texte")]
     Help,
-    #[command(description = "Lance une loterie")]
-    Roll,
-    #[command(description = "Réinitialise la loterie", hide)]
-    ResetRoll,
+    #[command(
+        description = "Lance une loterie: /roll [durée en secondes] [simple|somme] [sondage|clavier]"
+    )]
+    Roll(String),
+    #[command(
+        description = "Annule la loterie en cours et rembourse les mises",
+        aliases = ["resetroll"],
+        hide_aliases,
+        hide
+    )]
+    CancelRoll,
     #[command(description = "Regarde ton solde")]
     Balance,
+    #[command(
+        description = "Tes dernières transactions (ou celles de la personne à qui tu réponds)"
+    )]
+    History,
     #[command(description = "Classement des gens les plus riches")]
     Leaderboard,
-    #[command(description = "Renfloue le compte de quelqu'un", hide)]
-    Give,
+    #[command(description = "Touche ton allocation quotidienne")]
+    Daily,
+    #[command(description = "Demande à être renfloué quand tu es fauché")]
+    Bailout,
+    #[command(
+        description = "Paie un autre joueur: /pay <montant> @pseudo, ou en réponse à son message"
+    )]
+    Pay(String),
+    #[command(description = "Derniers tirages et statistiques de la loterie")]
+    LotoHistory,
+    #[command(
+        description = "Renfloue ou met à l'amende quelqu'un: /give <montant> [raison]",
+        hide
+    )]
+    Give(String),
+    #[command(description = "Mise pour ton prochain lancer: /bet <mise>")]
+    Bet(String),
+    #[command(description = "Mise par défaut de tes lancers: /stake <mise|reset>")]
+    Stake(String),
+    #[command(description = "Annonce le résultat de tes lancers de 🎲: /call <1-6|over|under>")]
+    Call(String),
+    #[command(description = "Gains des jeux dans cette conversation: /payouts [jeu]")]
+    Payouts(String),
+    #[command(
+        description = "Modifie un gain: /setpayout <jeu> <valeur du dé ou combinaison> <gain|reset>",
+        hide
+    )]
+    SetPayout(String),
+    #[command(description = "Active ou désactive le mode entraînement", hide)]
+    Practice,
+    #[command(
+        description = "Règles de la loterie: /setloto <participation> <solde minimum>",
+        hide
+    )]
+    SetLoto(String),
+    #[command(
+        description = "Programme une loterie quotidienne dans ce fil: /schedule <heure> [fuseau horaire] [durée] [simple|somme]",
+        hide
+    )]
+    Schedule(String),
+    #[command(
+        description = "Retire une loterie programmée: /unschedule <numéro>",
+        hide
+    )]
+    Unschedule(String),
+    #[command(
+        description = "Allocation quotidienne: /setdaily <montant> <bonus par jour de série> <jours de série max>",
+        hide
+    )]
+    SetDaily(String),
+    #[command(
+        description = "Renflouement des fauchés: /setbailout <montant> <délai en heures>",
+        hide
+    )]
+    SetBailout(String),
+}
+
+impl Command {
+    fn is_privileged(&self) -> bool {
+        matches!(
+            self,
+            Self::CancelRoll
+                | Self::Give(_)
+                | Self::SetPayout(_)
+                | Self::Practice
+                | Self::SetLoto(_)
+                | Self::Schedule(_)
+                | Self::Unschedule(_)
+                | Self::SetDaily(_)
+                | Self::SetBailout(_)
+        )
+    }
 }
 
 #[tokio::main]
@@ -50,15 +151,51 @@ async fn main() {
     if let Err(err) = bot.set_my_commands(Command::bot_commands()).await {
         warn!("Failed to set commands: {}", err);
     }
-    let poll_answers: Arc<Mutex<HashMap<UserId, u8>>> = Arc::new(Mutex::new(HashMap::default()));
+    let loto_polls: LotoPolls = Arc::new(Mutex::new(HashMap::default()));
+    let scheduler: SchedulerType = Arc::new(Scheduler::default());
+    let permissions: PermissionsType = Arc::new(Permissions::from_env());
+    let payouts: PayoutsType = match std::env::var("PAYOUTS_PATH") {
+        Ok(path) => Arc::new(
+            PayoutTables::load(&path)
+                .unwrap_or_else(|err| panic!("Invalid payout table {}: {}", path, err)),
+        ),
+        Err(_) => Arc::new(PayoutTables::default()),
+    };
 
     let path = std::env::var("DATABASE_PATH").unwrap_or_else(|_| "./database.db".to_string());
 
-    let storage: Arc<ErasedStorage<State>> =
-        SqliteStorage::open(&path, Json).await.unwrap().erase();
+    let storage: StorageType = SqliteStorage::open(&path, Json).await.unwrap().erase();
+
+    match get_stored_chat_ids(&path).await {
+        Ok(chat_ids) => {
+            resume_lotos(
+                bot.clone(),
+                storage.clone(),
+                loto_polls.clone(),
+                scheduler.clone(),
+                chat_ids.clone(),
+            )
+            .await;
+            resume_recurring_lotos(
+                bot.clone(),
+                storage.clone(),
+                loto_polls.clone(),
+                scheduler.clone(),
+                chat_ids,
+            )
+            .await
+        }
+        Err(err) => warn!("Failed to scan the storage for pending lotteries: {}", err),
+    }
 
     Dispatcher::builder(bot, schema())
-        .dependencies(dptree::deps![storage, poll_answers])
+        .dependencies(dptree::deps![
+            storage,
+            loto_polls,
+            scheduler,
+            permissions,
+            payouts
+        ])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
@@ -68,40 +205,60 @@ async fn main() {
 fn schema() -> UpdateHandler<Box<dyn Error + Send + Sync + 'static>> {
     use dptree::case;
 
+    let privileged_command_handler = dptree::filter(|command: Command| command.is_privileged())
+        .branch(
+            dptree::filter_async(is_authorized)
+                .branch(case![Command::CancelRoll].endpoint(cancel_roll))
+                .branch(case![Command::Give(args)].endpoint(give_money))
+                .branch(case![Command::SetPayout(args)].endpoint(set_payout))
+                .branch(case![Command::Practice].endpoint(toggle_practice_mode))
+                .branch(case![Command::SetLoto(args)].endpoint(set_loto_settings))
+                .branch(case![Command::Schedule(args)].endpoint(schedule_loto))
+                .branch(case![Command::Unschedule(args)].endpoint(unschedule_loto))
+                .branch(case![Command::SetDaily(args)].endpoint(set_daily))
+                .branch(case![Command::SetBailout(args)].endpoint(set_bailout)),
+        )
+        .branch(dptree::endpoint(permission_denied));
+
     let command_handler = teloxide::filter_command::<Command, _>()
+        .branch(privileged_command_handler)
         .branch(case![Command::Help].endpoint(help))
         .branch(case![Command::Balance].endpoint(balance))
+        .branch(case![Command::History].endpoint(history))
         .branch(case![Command::Leaderboard].endpoint(leaderboard))
-        .branch(case![Command::ResetRoll].endpoint(reset_roll))
-        .branch(case![Command::Give].endpoint(give_money))
-        .branch(
-            case![State::Idle { player_money }]
-                .branch(case![Command::Roll].endpoint(start_loto))
-                .branch(dptree::endpoint(invalid_state)),
-        );
+        .branch(case![Command::Daily].endpoint(daily))
+        .branch(case![Command::Bailout].endpoint(bailout))
+        .branch(case![Command::Pay(args)].endpoint(pay))
+        .branch(case![Command::LotoHistory].endpoint(loto_history))
+        .branch(case![Command::Bet(args)].endpoint(set_stake))
+        .branch(case![Command::Stake(args)].endpoint(set_default_stake))
+        .branch(case![Command::Call(args)].endpoint(set_dice_call))
+        .branch(case![Command::Payouts(args)].endpoint(show_payouts))
+        .branch(case![Command::Roll(args)].endpoint(start_loto));
 
     let message_handler = Update::filter_message()
         .branch(command_handler)
         .branch(dptree::endpoint(message_handler));
 
     let poll_handler = Update::filter_poll_answer().endpoint(register_answer);
+    let bet_handler = Update::filter_callback_query().endpoint(register_bet);
 
     dptree::entry()
         .branch(poll_handler)
+        .branch(bet_handler)
         .branch(dialogue::enter::<Update, ErasedStorage<State>, State, _>().branch(message_handler))
 }
 
-async fn message_handler(bot: BotType, dialogue: DialogueType, msg: Message) -> HandlerResult {
+async fn message_handler(
+    bot: BotType,
+    dialogue: DialogueType,
+    scheduler: SchedulerType,
+    payouts: PayoutsType,
+    msg: Message,
+) -> HandlerResult {
     if let MessageKind::Dice(_) = msg.kind {
-        emoji_games_handler(bot, dialogue, msg).await?;
+        emoji_games_handler(bot, dialogue, scheduler, payouts, msg).await?;
     }
 
     Ok(())
 }
-
-async fn invalid_state(bot: BotType, msg: Message) -> HandlerResult {
-    bot.send_message(msg.chat.id, "Commande indisponible, c'est pas bien")
-        .await?;
-
-    Ok(())
-}
@@ -0,0 +1,802 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt::Display,
+    ops::RangeInclusive,
+    str::FromStr,
+    sync::Arc,
+};
+
+use teloxide::types::DiceEmoji;
+
+use crate::ledger::Reason;
+use crate::state::ChatSettings;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Game {
+    SlotMachine,
+    Darts,
+    Basketball,
+    Bowling,
+    Football,
+    Dice,
+}
+
+impl Game {
+    pub(crate) const ALL: [Self; 6] = [
+        Self::SlotMachine,
+        Self::Darts,
+        Self::Basketball,
+        Self::Bowling,
+        Self::Football,
+        Self::Dice,
+    ];
+
+    pub(crate) fn from_emoji(emoji: DiceEmoji) -> Self {
+        match emoji {
+            DiceEmoji::SlotMachine => Self::SlotMachine,
+            DiceEmoji::Darts => Self::Darts,
+            DiceEmoji::Basketball => Self::Basketball,
+            DiceEmoji::Bowling => Self::Bowling,
+            DiceEmoji::Football => Self::Football,
+            DiceEmoji::Dice => Self::Dice,
+        }
+    }
+
+    // Every value Telegram can send for this game
+    pub(crate) fn values(&self) -> RangeInclusive<u8> {
+        match self {
+            Self::SlotMachine => 1..=64,
+            Self::Darts | Self::Bowling | Self::Dice => 1..=6,
+            Self::Basketball | Self::Football => 1..=5,
+        }
+    }
+
+    pub(crate) fn reason(&self) -> Reason {
+        match self {
+            Self::SlotMachine => Reason::SlotMachine,
+            Self::Darts => Reason::Darts,
+            Self::Basketball => Reason::Basketball,
+            Self::Bowling => Reason::Bowling,
+            Self::Football => Reason::Football,
+            Self::Dice => Reason::Dice,
+        }
+    }
+
+    fn default_payout(&self, value: u8) -> Payout {
+        let (reaction, score, delay) = match self {
+            Self::SlotMachine => unreachable!("The slot machine pays by combination"),
+            Self::Dice => unreachable!("The dice pays by call"),
+            Self::Darts => darts_handler(value),
+            Self::Basketball => basketball_handler(value),
+            Self::Bowling => bowling_handler(value),
+            Self::Football => football_handler(value),
+        };
+        Payout {
+            reaction: reaction.to_string(),
+            score,
+            delay,
+        }
+    }
+}
+
+impl Display for Game {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            Self::SlotMachine => "🎰 Machine à sous",
+            Self::Darts => "🎯 Fléchettes",
+            Self::Basketball => "🏀 Basket",
+            Self::Bowling => "🎳 Bowling",
+            Self::Football => "⚽ Foot",
+            Self::Dice => "🎲 Dé",
+        };
+        f.write_str(label)
+    }
+}
+
+impl FromStr for Game {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "slot" | "slots" | "slot_machine" | "🎰" => Ok(Self::SlotMachine),
+            "darts" | "dart" | "🎯" => Ok(Self::Darts),
+            "basketball" | "basket" | "🏀" => Ok(Self::Basketball),
+            "bowling" | "🎳" => Ok(Self::Bowling),
+            "football" | "foot" | "⚽" => Ok(Self::Football),
+            "dice" | "de" | "dé" | "🎲" => Ok(Self::Dice),
+            _ => Err(format!(
+                "Je ne connais pas le jeu {:?} (slot, darts, basketball, bowling, football, dice)",
+                name
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub(crate) struct Payout {
+    pub(crate) reaction: String,
+    pub(crate) score: i64,
+    pub(crate) delay: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct PayoutEntry {
+    value: u8,
+    #[serde(flatten)]
+    payout: Payout,
+}
+
+// What a player announces before throwing the 🎲
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DiceCall {
+    Number(u8),
+    Over,
+    Under,
+}
+
+impl DiceCall {
+    // The /setpayout key of the outcome: 1 for an exact number, 2 for over/under, 3 for a miss
+    fn key(&self, value: u8) -> u8 {
+        match self {
+            Self::Number(number) if *number == value => 1,
+            Self::Over if value > 3 => 2,
+            Self::Under if value <= 3 => 2,
+            _ => 3,
+        }
+    }
+}
+
+impl Display for DiceCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Number(number) => write!(f, "{}", number),
+            Self::Over => f.write_str("plus de 3"),
+            Self::Under => f.write_str("3 ou moins"),
+        }
+    }
+}
+
+impl FromStr for DiceCall {
+    type Err = String;
+
+    fn from_str(call: &str) -> Result<Self, Self::Err> {
+        match call.to_lowercase().as_str() {
+            "over" | "plus" | "+" => Ok(Self::Over),
+            "under" | "moins" | "-" => Ok(Self::Under),
+            number => match number.parse::<u8>() {
+                Ok(number) if Game::Dice.values().contains(&number) => Ok(Self::Number(number)),
+                _ => Err(format!(
+                    "{:?} n'est pas une annonce valide (1 à 6, over ou under)",
+                    call
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+struct DiceRules {
+    exact: Payout,
+    over_under: Payout,
+    miss: Payout,
+}
+
+impl DiceRules {
+    fn payouts(&self) -> [&Payout; 3] {
+        [&self.exact, &self.over_under, &self.miss]
+    }
+}
+
+impl Default for DiceRules {
+    fn default() -> Self {
+        let payout = |reaction: &str, score| Payout {
+            reaction: reaction.to_string(),
+            score,
+            delay: 4,
+        };
+        Self {
+            exact: payout("🤯", 4),
+            over_under: payout("👌", 1),
+            miss: payout("🙈", -1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SlotSymbol {
+    Bar,
+    Grapes,
+    Lemon,
+    Seven,
+}
+
+impl SlotSymbol {
+    // Telegram encodes the three reels as base 4 digits of the dice value, left reel first
+    pub(crate) fn decode(value: u8) -> [Self; 3] {
+        const SYMBOLS: [SlotSymbol; 4] = [
+            SlotSymbol::Bar,
+            SlotSymbol::Grapes,
+            SlotSymbol::Lemon,
+            SlotSymbol::Seven,
+        ];
+        let value = value - 1;
+        [
+            SYMBOLS[(value & 0b11) as usize],
+            SYMBOLS[((value >> 2) & 0b11) as usize],
+            SYMBOLS[((value >> 4) & 0b11) as usize],
+        ]
+    }
+}
+
+impl Display for SlotSymbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Self::Bar => "BAR",
+            Self::Grapes => "🍇",
+            Self::Lemon => "🍋",
+            Self::Seven => "7️⃣",
+        };
+        f.write_str(symbol)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SlotPattern {
+    Bar,
+    Grapes,
+    Lemon,
+    Seven,
+    Any,
+}
+
+impl SlotPattern {
+    fn symbol(&self) -> Option<SlotSymbol> {
+        match self {
+            Self::Bar => Some(SlotSymbol::Bar),
+            Self::Grapes => Some(SlotSymbol::Grapes),
+            Self::Lemon => Some(SlotSymbol::Lemon),
+            Self::Seven => Some(SlotSymbol::Seven),
+            Self::Any => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Bar => "bar",
+            Self::Grapes => "grapes",
+            Self::Lemon => "lemon",
+            Self::Seven => "seven",
+            Self::Any => "any",
+        }
+    }
+}
+
+// Matches the reels regardless of their order, the first matching rule of the table wins
+#[derive(Clone, Debug, serde::Deserialize)]
+struct SlotRule {
+    symbols: [SlotPattern; 3],
+    #[serde(flatten)]
+    payout: Payout,
+}
+
+impl SlotRule {
+    fn new(symbols: [SlotPattern; 3], reaction: &str, score: i64) -> Self {
+        Self {
+            symbols,
+            payout: Payout {
+                reaction: reaction.to_string(),
+                score,
+                delay: 2,
+            },
+        }
+    }
+
+    // Identifies the combination whatever the order of the rules and of its symbols, e.g. "seven seven any"
+    fn key(&self) -> String {
+        let mut symbols = self.symbols;
+        symbols.sort();
+        symbols.map(|pattern| pattern.name()).join(" ")
+    }
+
+    fn matches(&self, reels: [SlotSymbol; 3]) -> bool {
+        let mut remaining = reels.to_vec();
+        for symbol in self.symbols.iter().filter_map(SlotPattern::symbol) {
+            match remaining.iter().position(|&reel| reel == symbol) {
+                Some(position) => remaining.swap_remove(position),
+                None => return false,
+            };
+        }
+        true
+    }
+}
+
+impl Display for SlotRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbols = self
+            .symbols
+            .iter()
+            .map(|pattern| match pattern.symbol() {
+                Some(symbol) => symbol.to_string(),
+                None => "❔".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        f.write_str(&symbols)
+    }
+}
+
+fn default_slot_rules() -> Vec<SlotRule> {
+    use SlotPattern::*;
+
+    vec![
+        SlotRule::new([Seven, Seven, Seven], "🔥", 30),
+        SlotRule::new([Bar, Bar, Bar], "🎉", 10),
+        SlotRule::new([Grapes, Grapes, Grapes], "🎉", 10),
+        SlotRule::new([Lemon, Lemon, Lemon], "🎉", 10),
+        SlotRule::new([Seven, Seven, Any], "😢", -1),
+        SlotRule::new([Bar, Bar, Any], "😢", -1),
+        SlotRule::new([Grapes, Grapes, Any], "😢", -1),
+        SlotRule::new([Lemon, Lemon, Any], "😢", -1),
+        SlotRule::new([Any, Any, Any], "🥱", -1),
+    ]
+}
+
+#[derive(serde::Deserialize)]
+struct PayoutConfig {
+    slot_machine: Option<Vec<SlotRule>>,
+    dice: Option<DiceRules>,
+    #[serde(flatten)]
+    games: HashMap<Game, Vec<PayoutEntry>>,
+}
+
+pub(crate) struct PayoutTables {
+    dice: HashMap<Game, BTreeMap<u8, Payout>>,
+    slot_machine: Vec<SlotRule>,
+    dice_calls: DiceRules,
+}
+
+pub(crate) type PayoutsType = Arc<PayoutTables>;
+
+impl Default for PayoutTables {
+    fn default() -> Self {
+        let dice = Game::ALL
+            .iter()
+            .filter(|&&game| !matches!(game, Game::SlotMachine | Game::Dice))
+            .map(|game| {
+                let table = game
+                    .values()
+                    .map(|value| (value, game.default_payout(value)))
+                    .collect();
+                (*game, table)
+            })
+            .collect();
+
+        Self {
+            dice,
+            slot_machine: default_slot_rules(),
+            dice_calls: DiceRules::default(),
+        }
+    }
+}
+
+impl PayoutTables {
+    // Games missing from the file keep their default table
+    pub(crate) fn load(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    fn parse(config: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let config: PayoutConfig = toml::from_str(config)?;
+
+        let mut tables = Self::default();
+        if let Some(rules) = config.slot_machine {
+            if rules.len() > u8::MAX as usize {
+                return Err("The slot machine has too many rules".into());
+            }
+            if let Some(missing) = Game::SlotMachine.values().find(|&value| {
+                let reels = SlotSymbol::decode(value);
+                !rules.iter().any(|rule| rule.matches(reels))
+            }) {
+                let reels = SlotSymbol::decode(missing).map(|symbol| symbol.to_string());
+                return Err(format!("SlotMachine {} has no payout", reels.join(" ")).into());
+            }
+            let mut keys = rules.iter().map(SlotRule::key).collect::<Vec<_>>();
+            keys.sort();
+            if let Some(duplicate) = keys.windows(2).find(|pair| pair[0] == pair[1]) {
+                return Err(format!("SlotMachine {} is listed twice", duplicate[0]).into());
+            }
+            tables.slot_machine = rules;
+        }
+        if let Some(rules) = config.dice {
+            tables.dice_calls = rules;
+        }
+        for (game, entries) in config.games {
+            if matches!(game, Game::SlotMachine | Game::Dice) {
+                continue;
+            }
+            let mut table = BTreeMap::new();
+            for PayoutEntry { value, payout } in entries {
+                if !game.values().contains(&value) {
+                    return Err(format!("{:?} has no dice value {}", game, value).into());
+                }
+                if table.insert(value, payout).is_some() {
+                    return Err(format!("{:?} dice value {} is listed twice", game, value).into());
+                }
+            }
+            if let Some(missing) = game.values().find(|value| !table.contains_key(value)) {
+                return Err(format!("{:?} dice value {} has no payout", game, missing).into());
+            }
+            tables.dice.insert(game, table);
+        }
+        Ok(tables)
+    }
+
+    // What /setpayout refers to: the dice value, the rule number for the slot machine or the outcome for the 🎲
+    pub(crate) fn keys(&self, game: Game) -> RangeInclusive<u8> {
+        match game {
+            Game::SlotMachine => 1..=self.slot_machine.len() as u8,
+            Game::Dice => 1..=3,
+            _ => game.values(),
+        }
+    }
+
+    fn key(&self, game: Game, value: u8) -> Option<u8> {
+        match game {
+            Game::SlotMachine => {
+                let reels = SlotSymbol::decode(value);
+                let position = self
+                    .slot_machine
+                    .iter()
+                    .position(|rule| rule.matches(reels))?;
+                Some(position as u8 + 1)
+            }
+            Game::Dice => None,
+            _ => Some(value),
+        }
+    }
+
+    fn configured(&self, game: Game, key: u8) -> Option<&Payout> {
+        match game {
+            Game::SlotMachine => self
+                .slot_machine
+                .get(key as usize - 1)
+                .map(|rule| &rule.payout),
+            Game::Dice => self.dice_calls.payouts().get(key as usize - 1).copied(),
+            _ => self.dice.get(&game)?.get(&key),
+        }
+    }
+
+    fn override_score(&self, game: Game, key: u8, settings: &ChatSettings) -> Option<i64> {
+        match game {
+            Game::SlotMachine => {
+                let rule = self.slot_machine.get(key as usize - 1)?;
+                settings.slot_overrides.get(&rule.key()).copied()
+            }
+            _ => settings.payout_overrides.get(&game)?.get(&key).copied(),
+        }
+    }
+
+    fn with_override(&self, game: Game, key: u8, settings: &ChatSettings) -> Option<Payout> {
+        let mut payout = self.configured(game, key)?.clone();
+        if let Some(score) = self.override_score(game, key, settings) {
+            payout.score = score;
+        }
+        Some(payout)
+    }
+
+    // Sets or removes the chat's override of a /setpayout key
+    pub(crate) fn set_override(
+        &self,
+        game: Game,
+        key: u8,
+        score: Option<i64>,
+        settings: &mut ChatSettings,
+    ) {
+        match game {
+            Game::SlotMachine => {
+                let Some(rule) = self.slot_machine.get(key as usize - 1) else {
+                    return;
+                };
+                match score {
+                    Some(score) => settings.slot_overrides.insert(rule.key(), score),
+                    None => settings.slot_overrides.remove(&rule.key()),
+                };
+            }
+            _ => {
+                let overrides = settings.payout_overrides.entry(game).or_default();
+                match score {
+                    Some(score) => overrides.insert(key, score),
+                    None => overrides.remove(&key),
+                };
+            }
+        }
+    }
+
+    // Lowest score the game can give, a throw costs at most this much per 💵 staked
+    pub(crate) fn worst_score(&self, game: Game, settings: &ChatSettings) -> i64 {
+        self.keys(game)
+            .filter_map(|key| self.with_override(game, key, settings))
+            .map(|payout| payout.score)
+            .min()
+            .unwrap_or_default()
+    }
+
+    // The configured payout with the chat's score override applied
+    pub(crate) fn get(&self, game: Game, value: u8, settings: &ChatSettings) -> Option<Payout> {
+        self.with_override(game, self.key(game, value)?, settings)
+    }
+
+    pub(crate) fn get_dice(
+        &self,
+        call: DiceCall,
+        value: u8,
+        settings: &ChatSettings,
+    ) -> Option<Payout> {
+        self.with_override(Game::Dice, call.key(value), settings)
+    }
+
+    pub(crate) fn describe(&self, game: Game, settings: &ChatSettings) -> String {
+        let marker = |key: &u8| match self.override_score(game, *key, settings) {
+            Some(_) => " ✏️",
+            None => "",
+        };
+
+        let mut description = format!("{}:\n", game);
+        if game == Game::SlotMachine {
+            for (key, rule) in (1..).zip(&self.slot_machine) {
+                let Some(payout) = self.with_override(game, key, settings) else {
+                    continue;
+                };
+                description.push_str(&format!(
+                    "  {}. {}: {:+}💵{}\n",
+                    key,
+                    rule,
+                    payout.score,
+                    marker(&key)
+                ));
+            }
+            return description;
+        }
+        if game == Game::Dice {
+            let outcomes = ["Numéro exact", "Plus de 3 ou 3 ou moins", "Raté"];
+            for (key, outcome) in (1..).zip(outcomes) {
+                let Some(payout) = self.with_override(game, key, settings) else {
+                    continue;
+                };
+                description.push_str(&format!(
+                    "  {}. {}: {:+}💵{}\n",
+                    key,
+                    outcome,
+                    payout.score,
+                    marker(&key)
+                ));
+            }
+            return description;
+        }
+
+        // Group the dice values sharing the same score to keep the table short
+        let mut groups: BTreeMap<(i64, &str), Vec<u8>> = BTreeMap::new();
+        for value in game.values() {
+            let Some(payout) = self.get(game, value, settings) else {
+                continue;
+            };
+            groups
+                .entry((payout.score, marker(&value)))
+                .or_default()
+                .push(value);
+        }
+
+        for ((score, marker), values) in groups.iter().rev() {
+            let values = values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            description.push_str(&format!("  {}: {:+}💵{}\n", values, score, marker));
+        }
+        description
+    }
+}
+
+fn darts_handler(value: u8) -> (&'static str, i64, u64) {
+    let (reaction, score) = match value {
+        1 => ("🤡", -2),
+        2 => ("🥱", -2),
+        3 => ("🤔", -2),
+        4 => ("👀", -2),
+        5 => ("🙊", -2),
+        6 => ("😎", 12),
+        _ => unreachable!(),
+    };
+
+    (reaction, score, 3)
+}
+
+fn basketball_handler(value: u8) -> (&'static str, i64, u64) {
+    let (reaction, score) = match value {
+        1 => ("🫡", -3),
+        2 => ("🥱", -3),
+        3 => ("🥴", -3),
+        4 => ("🆒", 4),
+        5 => ("🤝", 6),
+        _ => unreachable!(),
+    };
+
+    (reaction, score, 4)
+}
+
+fn bowling_handler(value: u8) -> (&'static str, i64, u64) {
+    let (reaction, score) = match value {
+        1 => ("🌚", -3),
+        2 => ("👨‍💻", -3),
+        3 => ("🦄", -3),
+        4 => ("😨", -3),
+        5 => ("🤨", -3),
+        6 => ("🗿", 16),
+        _ => unreachable!(),
+    };
+
+    (reaction, score, 4)
+}
+
+fn football_handler(value: u8) -> (&'static str, i64, u64) {
+    let (reaction, score) = match value {
+        1 => ("🌭", -5),
+        2 => ("🐳", -5),
+        3 => ("💅", 1),
+        4 => ("🙏", 5),
+        5 => ("👏", 5),
+        _ => unreachable!(),
+    };
+
+    (reaction, score, 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn darts(values: &[u8]) -> String {
+        values
+            .iter()
+            .map(|value| {
+                format!(
+                    "[[darts]]\nvalue = {}\nreaction = \"🎯\"\nscore = {}\ndelay = 3\n",
+                    value, value
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loads_a_complete_table() {
+        let tables = PayoutTables::parse(&darts(&[1, 2, 3, 4, 5, 6])).unwrap();
+        let settings = ChatSettings::default();
+
+        let payout = tables.get(Game::Darts, 4, &settings).unwrap();
+        assert_eq!(payout.score, 4);
+        // Games missing from the file keep their defaults
+        let payout = tables.get(Game::Football, 1, &settings).unwrap();
+        assert_eq!(payout.score, -5);
+    }
+
+    #[test]
+    fn rejects_a_missing_value() {
+        let err = PayoutTables::parse(&darts(&[1, 2, 3, 4, 5])).err().unwrap();
+        assert_eq!(err.to_string(), "Darts dice value 6 has no payout");
+    }
+
+    #[test]
+    fn rejects_a_duplicate_value() {
+        let err = PayoutTables::parse(&darts(&[1, 2, 3, 3, 4, 5, 6]))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Darts dice value 3 is listed twice");
+    }
+
+    #[test]
+    fn rejects_an_impossible_value() {
+        let err = PayoutTables::parse(&darts(&[1, 2, 3, 4, 5, 6, 7]))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Darts has no dice value 7");
+    }
+
+    fn slot_rule(symbols: [&str; 3], score: i64) -> String {
+        format!(
+            "[[slot_machine]]\nsymbols = {:?}\nreaction = \"🎰\"\nscore = {}\ndelay = 2\n",
+            symbols, score
+        )
+    }
+
+    #[test]
+    fn decodes_the_slot_machine_reels() {
+        use SlotSymbol::*;
+
+        assert_eq!(SlotSymbol::decode(1), [Bar, Bar, Bar]);
+        assert_eq!(SlotSymbol::decode(22), [Grapes, Grapes, Grapes]);
+        assert_eq!(SlotSymbol::decode(43), [Lemon, Lemon, Lemon]);
+        assert_eq!(SlotSymbol::decode(64), [Seven, Seven, Seven]);
+        // The left reel is the lowest digit
+        assert_eq!(SlotSymbol::decode(2), [Grapes, Bar, Bar]);
+        assert_eq!(SlotSymbol::decode(17), [Bar, Bar, Grapes]);
+    }
+
+    #[test]
+    fn matches_slot_rules_in_any_order() {
+        let config = [
+            slot_rule(["seven", "seven", "any"], 5),
+            slot_rule(["any", "any", "any"], -1),
+        ]
+        .concat();
+        let tables = PayoutTables::parse(&config).unwrap();
+        let settings = ChatSettings::default();
+
+        // 7️⃣ BAR 7️⃣
+        let value = 1 + 3 + (3 << 4);
+        assert_eq!(
+            tables
+                .get(Game::SlotMachine, value, &settings)
+                .unwrap()
+                .score,
+            5
+        );
+        assert_eq!(
+            tables.get(Game::SlotMachine, 1, &settings).unwrap().score,
+            -1
+        );
+    }
+
+    #[test]
+    fn rejects_uncovered_slot_combinations() {
+        let config = ["bar", "grapes", "lemon", "seven"]
+            .map(|symbol| slot_rule([symbol; 3], 10))
+            .concat();
+        let err = PayoutTables::parse(&config).err().unwrap();
+        assert_eq!(err.to_string(), "SlotMachine 🍇 BAR BAR has no payout");
+    }
+
+    #[test]
+    fn slot_overrides_follow_their_combination() {
+        let config = [
+            slot_rule(["seven", "seven", "seven"], 30),
+            slot_rule(["bar", "bar", "bar"], 10),
+            slot_rule(["any", "any", "any"], -1),
+        ];
+        let tables = PayoutTables::parse(&config.concat()).unwrap();
+        let mut settings = ChatSettings::default();
+        tables.set_override(Game::SlotMachine, 1, Some(50), &mut settings);
+
+        // The payouts file is reordered, the override stays on the triple seven
+        let reordered = [&config[1], &config[0], &config[2]]
+            .map(String::as_str)
+            .concat();
+        let tables = PayoutTables::parse(&reordered).unwrap();
+        assert_eq!(
+            tables.get(Game::SlotMachine, 64, &settings).unwrap().score,
+            50
+        );
+        assert_eq!(
+            tables.get(Game::SlotMachine, 1, &settings).unwrap().score,
+            10
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_slot_combinations() {
+        let config = [
+            slot_rule(["seven", "any", "seven"], 5),
+            slot_rule(["seven", "seven", "any"], 5),
+            slot_rule(["any", "any", "any"], -1),
+        ];
+        let err = PayoutTables::parse(&config.concat()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "SlotMachine seven seven any is listed twice"
+        );
+    }
+}
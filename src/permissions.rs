@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use log::warn;
+use teloxide::{
+    payloads::SetMessageReactionSetters,
+    requests::Requester,
+    types::{ChatId, Message, ReactionType, UserId},
+};
+
+use crate::utils::{BotType, HandlerResult};
+
+const DEFAULT_OWNER: UserId = UserId(1908102113);
+
+pub(crate) struct Permissions {
+    owners: Vec<UserId>,
+    trust_chat_admins: bool,
+}
+
+pub(crate) type PermissionsType = Arc<Permissions>;
+
+impl Permissions {
+    // BOT_OWNERS is a comma separated list of user ids, TRUST_CHAT_ADMINS lets chat administrators in
+    pub(crate) fn from_env() -> Self {
+        let owners = match std::env::var("BOT_OWNERS") {
+            Ok(owners) => owners
+                .split(',')
+                .filter(|owner| !owner.trim().is_empty())
+                .filter_map(|owner| match owner.trim().parse() {
+                    Ok(owner) => Some(UserId(owner)),
+                    Err(err) => {
+                        warn!("Ignoring invalid bot owner {:?}: {}", owner, err);
+                        None
+                    }
+                })
+                .collect(),
+            Err(_) => vec![DEFAULT_OWNER],
+        };
+        let trust_chat_admins = std::env::var("TRUST_CHAT_ADMINS")
+            .is_ok_and(|trust| matches!(trust.as_str(), "1" | "true" | "yes"));
+
+        Self {
+            owners,
+            trust_chat_admins,
+        }
+    }
+
+    pub(crate) async fn is_privileged(&self, bot: &BotType, chat_id: ChatId, user: UserId) -> bool {
+        if self.owners.contains(&user) {
+            return true;
+        }
+        if !self.trust_chat_admins {
+            return false;
+        }
+
+        match bot.get_chat_administrators(chat_id).await {
+            Ok(administrators) => administrators
+                .iter()
+                .any(|administrator| administrator.user.id == user),
+            Err(err) => {
+                warn!(
+                    "Failed to fetch the administrators of chat {}: {}",
+                    chat_id, err
+                );
+                false
+            }
+        }
+    }
+}
+
+pub(crate) async fn is_authorized(
+    bot: BotType,
+    permissions: PermissionsType,
+    msg: Message,
+) -> bool {
+    match msg.from {
+        Some(user) => permissions.is_privileged(&bot, msg.chat.id, user.id).await,
+        None => false,
+    }
+}
+
+pub(crate) async fn permission_denied(bot: BotType, msg: Message) -> HandlerResult {
+    bot.set_message_reaction(msg.chat.id, msg.id)
+        .reaction(vec![ReactionType::Emoji {
+            emoji: "🤣".to_string(),
+        }])
+        .await?;
+
+    Ok(())
+}
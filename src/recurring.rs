@@ -0,0 +1,305 @@
+use std::{fmt::Display, sync::Arc};
+
+use chrono::{DateTime, Days, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use log::{info, warn};
+use teloxide::types::{ChatId, Message, ThreadId};
+
+use crate::loto::{open_loto, LotoPolls, LotoRound};
+use crate::scheduler::{JobHandle, JobKind, SchedulerType};
+use crate::utils::{
+    reply_to, update_state, BotType, DialogueType, HandlerResult, StorageType, DEFAULT_TIMEZONE,
+};
+
+const MAX_SCHEDULES: usize = 10;
+
+// A lottery started every day at the same local time, in the thread it was scheduled from
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct LotoSchedule {
+    time: NaiveTime,
+    timezone: Tz,
+    thread_id: Option<ThreadId>,
+    round: LotoRound,
+}
+
+impl LotoSchedule {
+    fn next_occurrence(&self, after: DateTime<Utc>) -> DateTime<Utc> {
+        let mut date = after.with_timezone(&self.timezone).date_naive();
+        loop {
+            // Times skipped by a daylight saving change have no occurrence that day
+            if let Some(occurrence) = self
+                .timezone
+                .from_local_datetime(&date.and_time(self.time))
+                .earliest()
+                .map(|occurrence| occurrence.with_timezone(&Utc))
+                .filter(|&occurrence| occurrence > after)
+            {
+                return occurrence;
+            }
+            date = date + Days::new(1);
+        }
+    }
+}
+
+impl Display for LotoSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tous les jours à {} ({}), {}",
+            self.time.format("%H:%M"),
+            self.timezone,
+            self.round
+        )
+    }
+}
+
+fn parse_schedule(args: &str, thread_id: Option<ThreadId>) -> Result<LotoSchedule, String> {
+    let mut args = args.split_whitespace().peekable();
+    let time = args.next().unwrap_or_default();
+    let time = NaiveTime::parse_from_str(time, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%Hh%M"))
+        .map_err(|_| format!("{:?} n'est pas une heure valide (21:00)", time))?;
+    let timezone = match args.peek().and_then(|timezone| timezone.parse::<Tz>().ok()) {
+        Some(timezone) => {
+            args.next();
+            timezone
+        }
+        None => DEFAULT_TIMEZONE,
+    };
+    let round = args.collect::<Vec<_>>().join(" ").parse()?;
+
+    Ok(LotoSchedule {
+        time,
+        timezone,
+        thread_id,
+        round,
+    })
+}
+
+pub(crate) async fn schedule_loto(
+    bot: BotType,
+    dialogue: DialogueType,
+    loto_polls: LotoPolls,
+    scheduler: SchedulerType,
+    msg: Message,
+    args: String,
+) -> HandlerResult {
+    const USAGE: &str =
+        "Utilisation: /schedule <heure> [fuseau horaire] [durée en secondes] [simple|somme]";
+
+    let schedule = match args.trim() {
+        "" => None,
+        args => match parse_schedule(args, msg.thread_id) {
+            Ok(schedule) => Some(schedule),
+            Err(err) => return reply_to(&bot, &msg, format!("{}\n{}", err, USAGE)).await,
+        },
+    };
+
+    let schedules = update_state(&dialogue, |state| {
+        let schedules = &mut state.settings_mut().loto_schedules;
+        match schedule {
+            Some(_) if schedules.len() >= MAX_SCHEDULES => Err(format!(
+                "Pas plus de {} loteries programmées par conversation",
+                MAX_SCHEDULES
+            )),
+            Some(schedule) => {
+                schedules.push(schedule);
+                Ok(schedules.clone())
+            }
+            None => Ok(schedules.clone()),
+        }
+    })
+    .await?;
+    let schedules = match schedules {
+        Ok(schedules) => schedules,
+        Err(err) => return reply_to(&bot, &msg, err).await,
+    };
+    register_recurring_lotos(&bot, &dialogue, &loto_polls, &scheduler, &schedules);
+
+    reply_to(&bot, &msg, describe_schedules(&schedules)).await
+}
+
+pub(crate) async fn unschedule_loto(
+    bot: BotType,
+    dialogue: DialogueType,
+    loto_polls: LotoPolls,
+    scheduler: SchedulerType,
+    msg: Message,
+    args: String,
+) -> HandlerResult {
+    let Ok(number) = args.trim().parse::<usize>() else {
+        let usage = "Utilisation: /unschedule <numéro de la loterie programmée> (voir /schedule)";
+        return reply_to(&bot, &msg, usage).await;
+    };
+
+    let schedules = update_state(&dialogue, |state| {
+        let schedules = &mut state.settings_mut().loto_schedules;
+        if !(1..=schedules.len()).contains(&number) {
+            return None;
+        }
+        schedules.remove(number - 1);
+        Some(schedules.clone())
+    })
+    .await?;
+    let Some(schedules) = schedules else {
+        let error = format!("Il n'y a pas de loterie programmée n°{}", number);
+        return reply_to(&bot, &msg, error).await;
+    };
+    register_recurring_lotos(&bot, &dialogue, &loto_polls, &scheduler, &schedules);
+
+    reply_to(&bot, &msg, describe_schedules(&schedules)).await
+}
+
+fn describe_schedules(schedules: &[LotoSchedule]) -> String {
+    if schedules.is_empty() {
+        return "Aucune loterie programmée".to_string();
+    }
+    let mut description = "Loteries programmées:\n".to_string();
+    for (number, schedule) in (1..).zip(schedules) {
+        description.push_str(&format!("{}. {}\n", number, schedule));
+    }
+    description
+}
+
+pub(crate) async fn resume_recurring_lotos(
+    bot: BotType,
+    storage: StorageType,
+    loto_polls: LotoPolls,
+    scheduler: SchedulerType,
+    chat_ids: Vec<ChatId>,
+) {
+    for chat_id in chat_ids {
+        let dialogue = DialogueType::new(storage.clone(), chat_id);
+        match dialogue.get().await {
+            Ok(Some(state)) if !state.settings().loto_schedules.is_empty() => {
+                info!("Scheduling recurring lotteries in chat {}", chat_id);
+                let schedules = &state.settings().loto_schedules;
+                register_recurring_lotos(&bot, &dialogue, &loto_polls, &scheduler, schedules);
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Failed to read the state of chat {}: {}", chat_id, err),
+        }
+    }
+}
+
+// Replaces every recurring lottery job of the chat by the given schedules
+fn register_recurring_lotos(
+    bot: &BotType,
+    dialogue: &DialogueType,
+    loto_polls: &LotoPolls,
+    scheduler: &SchedulerType,
+    schedules: &[LotoSchedule],
+) {
+    scheduler.cancel(dialogue.chat_id(), JobKind::RecurringLoto);
+    for schedule in schedules {
+        schedule_next(
+            bot.clone(),
+            dialogue.clone(),
+            loto_polls.clone(),
+            scheduler,
+            schedule.clone(),
+            Utc::now(),
+        );
+    }
+}
+
+fn schedule_next(
+    bot: BotType,
+    dialogue: DialogueType,
+    loto_polls: LotoPolls,
+    scheduler: &SchedulerType,
+    schedule: LotoSchedule,
+    after: DateTime<Utc>,
+) -> JobHandle {
+    let chat_id = dialogue.chat_id();
+    let occurrence = schedule.next_occurrence(after);
+    let delay = (occurrence - Utc::now()).to_std().unwrap_or_default();
+    let job_scheduler = Arc::clone(scheduler);
+    scheduler.schedule(chat_id, JobKind::RecurringLoto, delay, async move {
+        schedule_next(
+            bot.clone(),
+            dialogue.clone(),
+            loto_polls.clone(),
+            &job_scheduler,
+            schedule.clone(),
+            occurrence,
+        );
+
+        let LotoSchedule {
+            thread_id, round, ..
+        } = schedule;
+        match open_loto(bot, dialogue, loto_polls, &job_scheduler, thread_id, round).await {
+            Ok(true) => {}
+            Ok(false) => info!(
+                "Skipping the recurring lottery of chat {}, one is already running",
+                chat_id
+            ),
+            Err(err) => warn!(
+                "Failed to start the recurring lottery of chat {}: {}",
+                chat_id, err
+            ),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn daily_at(time: &str) -> LotoSchedule {
+        parse_schedule(time, None).unwrap()
+    }
+
+    fn utc(datetime: &str) -> DateTime<Utc> {
+        datetime.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_schedules() {
+        let schedule = parse_schedule("21h30 America/New_York 120 somme", None).unwrap();
+        assert_eq!(schedule.time, NaiveTime::from_hms_opt(21, 30, 0).unwrap());
+        assert_eq!(schedule.timezone, chrono_tz::America::New_York);
+        assert_eq!(schedule.round.duration, 120);
+
+        assert_eq!(daily_at("21:00").timezone, DEFAULT_TIMEZONE);
+        assert!(parse_schedule("25:00", None).is_err());
+        assert!(parse_schedule("", None).is_err());
+    }
+
+    #[test]
+    fn keeps_the_local_time_across_daylight_saving_changes() {
+        let schedule = daily_at("21:00");
+        // 21:00 in Paris is 20:00 UTC in winter and 19:00 UTC in summer
+        assert_eq!(
+            schedule.next_occurrence(utc("2026-03-28T12:00:00Z")),
+            utc("2026-03-28T20:00:00Z")
+        );
+        assert_eq!(
+            schedule.next_occurrence(utc("2026-03-28T20:00:00Z")),
+            utc("2026-03-29T19:00:00Z")
+        );
+        assert_eq!(
+            schedule.next_occurrence(utc("2026-10-24T19:00:00Z")),
+            utc("2026-10-25T20:00:00Z")
+        );
+    }
+
+    #[test]
+    fn skips_times_missing_on_the_spring_change() {
+        // 02:30 does not exist in Paris on the 29th of March 2026
+        let schedule = daily_at("02:30");
+        assert_eq!(
+            schedule.next_occurrence(utc("2026-03-28T12:00:00Z")),
+            utc("2026-03-30T00:30:00Z")
+        );
+    }
+
+    #[test]
+    fn runs_once_on_the_autumn_change() {
+        // 02:30 happens twice in Paris on the 25th of October 2026
+        let schedule = daily_at("02:30");
+        let first = schedule.next_occurrence(utc("2026-10-24T12:00:00Z"));
+        assert_eq!(first, utc("2026-10-25T00:30:00Z"));
+        assert_eq!(schedule.next_occurrence(first), utc("2026-10-26T01:30:00Z"));
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use teloxide::types::ChatId;
+use tokio::task::AbortHandle;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum JobKind {
+    LotoDraw,
+    Reaction,
+    RecurringLoto,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct JobHandle {
+    id: u64,
+    abort_handle: AbortHandle,
+}
+
+// Delayed jobs that have not started yet, they can be cancelled until their delay elapses
+#[derive(Default)]
+pub(crate) struct Scheduler {
+    next_id: AtomicU64,
+    jobs: Mutex<HashMap<(ChatId, JobKind), Vec<JobHandle>>>,
+}
+
+pub(crate) type SchedulerType = Arc<Scheduler>;
+
+impl Scheduler {
+    pub(crate) fn schedule<F>(
+        self: &Arc<Self>,
+        chat_id: ChatId,
+        kind: JobKind,
+        delay: Duration,
+        job: F,
+    ) -> JobHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let scheduler = Arc::clone(self);
+
+        // Hold the lock until the job is registered so that it cannot start unregistered
+        let mut jobs = self.jobs.lock().unwrap_or_else(|err| err.into_inner());
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if scheduler.take(chat_id, kind, id) {
+                job.await;
+            }
+        });
+        let handle = JobHandle {
+            id,
+            abort_handle: task.abort_handle(),
+        };
+        jobs.entry((chat_id, kind))
+            .or_default()
+            .push(handle.clone());
+        handle
+    }
+
+    pub(crate) fn cancel(&self, chat_id: ChatId, kind: JobKind) -> Vec<JobHandle> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|err| err.into_inner());
+        let cancelled = jobs.remove(&(chat_id, kind)).unwrap_or_default();
+        for handle in &cancelled {
+            handle.abort_handle.abort();
+        }
+        cancelled
+    }
+
+    // Unregisters a job whose delay elapsed, returns false if it was cancelled in the meantime
+    fn take(&self, chat_id: ChatId, kind: JobKind, id: u64) -> bool {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|err| err.into_inner());
+        let Some(handles) = jobs.get_mut(&(chat_id, kind)) else {
+            return false;
+        };
+        let Some(position) = handles.iter().position(|handle| handle.id == id) else {
+            return false;
+        };
+        handles.swap_remove(position);
+        if handles.is_empty() {
+            jobs.remove(&(chat_id, kind));
+        }
+        true
+    }
+}
@@ -1,17 +1,173 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use teloxide::types::{Message, UserId};
+use chrono::{DateTime, NaiveDate, Utc};
+use teloxide::types::{ChatId, Message, UserId};
+
+use crate::ledger::{Ledger, Reason, Transaction};
+use crate::loto::LotoMode;
+use crate::loto_history::LotoHistory;
+use crate::payouts::{DiceCall, Game};
+use crate::recurring::LotoSchedule;
 
 const DEFAULT_MONEY: i64 = 100;
 
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub(crate) struct ChatSettings {
+    // Scores replacing the configured payout table, by game and dice value or 🎲 outcome
+    #[serde(default)]
+    pub(crate) payout_overrides: HashMap<Game, BTreeMap<u8, i64>>,
+    // Slot machine scores by combination, so that reordering the payouts file keeps them in place
+    #[serde(default)]
+    pub(crate) slot_overrides: BTreeMap<String, i64>,
+    // Games are played for fun only, balances are left untouched
+    #[serde(default)]
+    pub(crate) practice_mode: bool,
+    #[serde(default)]
+    pub(crate) loto: LotoSettings,
+    #[serde(default)]
+    pub(crate) loto_schedules: Vec<LotoSchedule>,
+    #[serde(default)]
+    pub(crate) daily: DailySettings,
+    #[serde(default)]
+    pub(crate) bailout: BailoutSettings,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct BailoutSettings {
+    pub(crate) amount: i64,
+    // How long a player must stay broke before being bailed out
+    pub(crate) cooldown_hours: i64,
+}
+
+impl Default for BailoutSettings {
+    fn default() -> Self {
+        Self {
+            amount: 50,
+            cooldown_hours: 24,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct DailySettings {
+    pub(crate) amount: i64,
+    // Added for each consecutive day after the first
+    pub(crate) streak_bonus: i64,
+    // The bonus stops growing after this many consecutive days
+    pub(crate) max_streak: u32,
+}
+
+impl Default for DailySettings {
+    fn default() -> Self {
+        Self {
+            amount: 20,
+            streak_bonus: 5,
+            max_streak: 7,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct LotoSettings {
+    // Paid into the pot by every participant
+    pub(crate) entry_fee: i64,
+    // Players below this balance neither win nor pay
+    pub(crate) min_balance: i64,
+    // Lotteries with fewer bets are cancelled at the deadline
+    #[serde(default = "default_min_participants")]
+    pub(crate) min_participants: usize,
+}
+
+fn default_min_participants() -> usize {
+    2
+}
+
+impl Default for LotoSettings {
+    fn default() -> Self {
+        Self {
+            entry_fee: 10,
+            min_balance: 10,
+            min_participants: default_min_participants(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub(crate) struct PlayerProfile {
+    // Stake applied to every emoji game unless a one-off stake is set
+    #[serde(default)]
+    pub(crate) default_stake: Option<i64>,
+    // Stake for the next emoji game only
+    #[serde(default)]
+    pub(crate) next_stake: Option<i64>,
+    // Announced outcome of the next 🎲 throws
+    #[serde(default)]
+    pub(crate) dice_call: Option<DiceCall>,
+    // Calendar day of the last /daily claim
+    #[serde(default)]
+    pub(crate) last_daily: Option<NaiveDate>,
+    // Consecutive days ending with the last claim
+    #[serde(default)]
+    pub(crate) daily_streak: u32,
+    // Since when the balance has been at or below zero
+    #[serde(default)]
+    pub(crate) broke_since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub(crate) bailouts: u32,
+}
+
+impl PlayerProfile {
+    pub(crate) fn stake(&self) -> i64 {
+        self.next_stake.or(self.default_stake).unwrap_or(1)
+    }
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub(crate) enum State {
     Idle {
         player_money: HashMap<UserId, i64>,
+        #[serde(default)]
+        ledger: Ledger,
+        #[serde(default)]
+        settings: ChatSettings,
+        #[serde(default)]
+        profiles: HashMap<UserId, PlayerProfile>,
+        // Pot left over by lotteries nobody won
+        #[serde(default)]
+        jackpot: i64,
+        #[serde(default)]
+        loto_history: LotoHistory,
     },
     ReceivingPollAnswers {
-        poll: Message,
+        // None while the ballot is being sent
+        poll: Option<Box<Message>>,
+        #[serde(default)]
+        bets: HashMap<UserId, u8>,
+        // Amounts already taken from the players who bet with the keyboard
+        #[serde(default)]
+        stakes: HashMap<UserId, i64>,
+        // Set when the draw starts, the bets can no longer change
+        #[serde(default)]
+        closed: bool,
+        #[serde(default)]
+        deadline: DateTime<Utc>,
+        // Settings of the chat when the lottery started, later changes apply to the next one
+        #[serde(default)]
+        rules: LotoSettings,
+        #[serde(default)]
+        mode: LotoMode,
         player_money: HashMap<UserId, i64>,
+        #[serde(default)]
+        ledger: Ledger,
+        #[serde(default)]
+        settings: ChatSettings,
+        #[serde(default)]
+        profiles: HashMap<UserId, PlayerProfile>,
+        // Pot left over by lotteries nobody won
+        #[serde(default)]
+        jackpot: i64,
+        #[serde(default)]
+        loto_history: LotoHistory,
     },
 }
 
@@ -19,6 +175,11 @@ impl Default for State {
     fn default() -> Self {
         Self::Idle {
             player_money: HashMap::default(),
+            ledger: Ledger::default(),
+            settings: ChatSettings::default(),
+            profiles: HashMap::default(),
+            jackpot: 0,
+            loto_history: LotoHistory::default(),
         }
     }
 }
@@ -26,21 +187,88 @@ impl Default for State {
 impl State {
     fn player_money(&self) -> &HashMap<UserId, i64> {
         match self {
-            Self::Idle { player_money } => player_money,
-            Self::ReceivingPollAnswers {
-                poll: _,
-                player_money,
-            } => player_money,
+            Self::Idle { player_money, .. } => player_money,
+            Self::ReceivingPollAnswers { player_money, .. } => player_money,
         }
     }
 
-    fn player_money_mut(&mut self) -> &mut HashMap<UserId, i64> {
+    fn ledger(&self) -> &Ledger {
         match self {
-            Self::Idle { player_money } => player_money,
+            Self::Idle { ledger, .. } => ledger,
+            Self::ReceivingPollAnswers { ledger, .. } => ledger,
+        }
+    }
+
+    pub(crate) fn settings(&self) -> &ChatSettings {
+        match self {
+            Self::Idle { settings, .. } => settings,
+            Self::ReceivingPollAnswers { settings, .. } => settings,
+        }
+    }
+
+    pub(crate) fn settings_mut(&mut self) -> &mut ChatSettings {
+        match self {
+            Self::Idle { settings, .. } => settings,
+            Self::ReceivingPollAnswers { settings, .. } => settings,
+        }
+    }
+
+    pub(crate) fn profile(&self, player: &UserId) -> PlayerProfile {
+        let profiles = match self {
+            Self::Idle { profiles, .. } => profiles,
+            Self::ReceivingPollAnswers { profiles, .. } => profiles,
+        };
+        profiles.get(player).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn profile_mut(&mut self, player: &UserId) -> &mut PlayerProfile {
+        let profiles = match self {
+            Self::Idle { profiles, .. } => profiles,
+            Self::ReceivingPollAnswers { profiles, .. } => profiles,
+        };
+        profiles.entry(*player).or_default()
+    }
+
+    pub(crate) fn jackpot(&self) -> i64 {
+        match self {
+            Self::Idle { jackpot, .. } => *jackpot,
+            Self::ReceivingPollAnswers { jackpot, .. } => *jackpot,
+        }
+    }
+
+    pub(crate) fn set_jackpot(&mut self, amount: i64) {
+        match self {
+            Self::Idle { jackpot, .. } => *jackpot = amount,
+            Self::ReceivingPollAnswers { jackpot, .. } => *jackpot = amount,
+        }
+    }
+
+    pub(crate) fn loto_history(&self) -> &LotoHistory {
+        match self {
+            Self::Idle { loto_history, .. } => loto_history,
+            Self::ReceivingPollAnswers { loto_history, .. } => loto_history,
+        }
+    }
+
+    pub(crate) fn loto_history_mut(&mut self) -> &mut LotoHistory {
+        match self {
+            Self::Idle { loto_history, .. } => loto_history,
+            Self::ReceivingPollAnswers { loto_history, .. } => loto_history,
+        }
+    }
+
+    fn accounts_mut(&mut self) -> (&mut HashMap<UserId, i64>, &mut Ledger) {
+        match self {
+            Self::Idle {
+                player_money,
+                ledger,
+                ..
+            } => (player_money, ledger),
             Self::ReceivingPollAnswers {
-                poll: _,
                 player_money,
-            } => player_money,
+                ledger,
+                ..
+            } => (player_money, ledger),
         }
     }
 
@@ -57,28 +285,106 @@ impl State {
         self.player_money().get(player).unwrap_or(&DEFAULT_MONEY)
     }
 
-    pub(crate) fn insert(&mut self, player: &UserId, delta_money: i64) {
-        let player_money = self.player_money_mut();
-        player_money.insert(
-            *player,
-            player_money.get(&player).unwrap_or(&DEFAULT_MONEY) + delta_money,
+    pub(crate) fn history(&self, player: &UserId, count: usize) -> Vec<&Transaction> {
+        self.ledger().history(player, count)
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        chat: ChatId,
+        player: &UserId,
+        delta_money: i64,
+        reason: Reason,
+    ) {
+        self.insert_with_note(chat, player, delta_money, reason, None);
+    }
+
+    pub(crate) fn insert_with_note(
+        &mut self,
+        chat: ChatId,
+        player: &UserId,
+        delta_money: i64,
+        reason: Reason,
+        note: Option<String>,
+    ) {
+        let (player_money, ledger) = self.accounts_mut();
+        let balance = *player_money.get(player).unwrap_or(&DEFAULT_MONEY);
+
+        // Balances predating the ledger are recorded once so that they stay derivable from it
+        if !ledger.has_entries(player) {
+            ledger.record(chat, player, Reason::Opening, balance, balance, None);
+        }
+
+        ledger.record(
+            chat,
+            player,
+            reason,
+            delta_money,
+            balance + delta_money,
+            note,
         );
+        player_money.insert(*player, balance + delta_money);
+        debug_assert_eq!(ledger.balance(player), Some(balance + delta_money));
+
+        let profile = self.profile_mut(player);
+        if balance + delta_money > 0 {
+            profile.broke_since = None;
+        } else if profile.broke_since.is_none() {
+            profile.broke_since = Some(Utc::now());
+        }
     }
 
-    pub(crate) fn to_idle(self) -> Self {
+    pub(crate) fn into_idle(self) -> Self {
         match self {
             Self::Idle { .. } => self,
             Self::ReceivingPollAnswers {
-                poll: _,
                 player_money,
-            } => Self::Idle { player_money },
+                ledger,
+                settings,
+                profiles,
+                jackpot,
+                loto_history,
+                ..
+            } => Self::Idle {
+                player_money,
+                ledger,
+                settings,
+                profiles,
+                jackpot,
+                loto_history,
+            },
         }
     }
 
-    pub(crate) fn to_receiving_poll_answers(self, poll: Message) -> Self {
+    pub(crate) fn into_receiving_poll_answers(
+        self,
+        deadline: DateTime<Utc>,
+        mode: LotoMode,
+    ) -> Self {
         match self {
             Self::ReceivingPollAnswers { .. } => self,
-            Self::Idle { player_money } => Self::ReceivingPollAnswers { poll, player_money },
+            Self::Idle {
+                player_money,
+                ledger,
+                settings,
+                profiles,
+                jackpot,
+                loto_history,
+            } => Self::ReceivingPollAnswers {
+                poll: None,
+                bets: HashMap::default(),
+                stakes: HashMap::default(),
+                closed: false,
+                deadline,
+                rules: settings.loto.clone(),
+                mode,
+                player_money,
+                ledger,
+                settings,
+                profiles,
+                jackpot,
+                loto_history,
+            },
         }
     }
 }
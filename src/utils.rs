@@ -1,11 +1,17 @@
-use std::error::Error;
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::{Arc, LazyLock, Mutex},
+};
 
+use sqlx::SqlitePool;
 use teloxide::{
     adaptors::Throttle,
     dispatching::dialogue::ErasedStorage,
+    payloads::SendMessageSetters,
     prelude::Dialogue,
     requests::Requester,
-    types::{ChatId, UserId},
+    types::{ChatId, Message, ReplyParameters, UserId},
     Bot,
 };
 
@@ -14,6 +20,48 @@ use crate::state::State;
 pub(crate) type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
 pub(crate) type BotType = Throttle<Bot>;
 pub(crate) type DialogueType = Dialogue<State, ErasedStorage<State>>;
+pub(crate) type StorageType = Arc<ErasedStorage<State>>;
+
+// Timezone of the calendar days and schedules of the bot
+pub(crate) const DEFAULT_TIMEZONE: chrono_tz::Tz = chrono_tz::Europe::Paris;
+
+// One lock per chat, held by `update_state` from the moment the state is read until it is written back
+static CHAT_LOCKS: LazyLock<Mutex<HashMap<ChatId, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Mutex::default);
+
+pub(crate) async fn update_state<R>(
+    dialogue: &DialogueType,
+    update: impl FnOnce(&mut State) -> R,
+) -> Result<R, Box<dyn Error + Send + Sync>> {
+    let chat_lock = CHAT_LOCKS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .entry(dialogue.chat_id())
+        .or_default()
+        .clone();
+    let _guard = chat_lock.lock().await;
+
+    let mut state = dialogue.get().await?.unwrap_or_default();
+    let result = update(&mut state);
+    dialogue.update(state).await?;
+    Ok(result)
+}
+
+pub(crate) async fn reply_to(
+    bot: &BotType,
+    msg: &Message,
+    text: impl Into<String>,
+) -> HandlerResult {
+    let mut message = bot
+        .send_message(msg.chat.id, text)
+        .reply_parameters(ReplyParameters::new(msg.id));
+    if let Some(thread_msg_id) = msg.thread_id {
+        message = message.message_thread_id(thread_msg_id);
+    }
+    message.await?;
+
+    Ok(())
+}
 
 pub(crate) async fn get_username(
     bot: &BotType,
@@ -22,7 +70,7 @@ pub(crate) async fn get_username(
 ) -> Result<String, Box<dyn Error>> {
     let member = bot.get_chat_member(chat_id, *user_id).await?;
     if member.user.is_anonymous() {
-        return Ok(member.custom_title().unwrap_or("Annoymous".into()).into());
+        return Ok(member.custom_title().unwrap_or("Annoymous").into());
     }
     match member.user.username {
         Some(username) => Ok(username),
@@ -32,10 +80,24 @@ pub(crate) async fn get_username(
 
 pub(crate) async fn get_usernames(bot: &BotType, chat_id: &ChatId, ids: &[UserId]) -> Vec<String> {
     let mut winners = vec![];
-    for id in ids.into_iter() {
-        if let Ok(username) = get_username(bot, *chat_id, &id).await {
+    for id in ids.iter() {
+        if let Ok(username) = get_username(bot, *chat_id, id).await {
             winners.push(username);
         }
     }
     winners
 }
+
+pub(crate) async fn get_stored_chat_ids(
+    path: &str,
+) -> Result<Vec<ChatId>, Box<dyn Error + Send + Sync>> {
+    let pool = SqlitePool::connect(&format!("sqlite:{path}?mode=rwc")).await?;
+    let chat_ids: Vec<(i64,)> = sqlx::query_as("SELECT chat_id FROM teloxide_dialogues")
+        .fetch_all(&pool)
+        .await?;
+    pool.close().await;
+    Ok(chat_ids
+        .into_iter()
+        .map(|(chat_id,)| ChatId(chat_id))
+        .collect())
+}